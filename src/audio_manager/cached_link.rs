@@ -0,0 +1,276 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use reqwest::Client;
+use songbird::input::Input;
+use tracing::{event, Level};
+use youtube_dl::YoutubeDl;
+
+use crate::{
+    cache_manager::{cache_saver::CacheSaver, CacheableSong, CachedSong},
+    common::{Song, SongId},
+};
+
+use super::Downloader;
+
+/// Added to the file name of a song that is downloaded again until it replaces the broken file
+const REDOWNLOAD_SUFFIX: &str = "redownload";
+
+/// A cached song of a link, streamed and downloaded again when its file is missing or corrupt
+pub struct CachedLink<CS>
+where
+    CS: CacheSaver + Send + Sync + 'static,
+{
+    song: CachedSong,
+    downloader: Downloader<CS>,
+    yt_dlp_path: &'static str,
+    client: Client,
+}
+
+impl<CS> Clone for CachedLink<CS>
+where
+    CS: CacheSaver + Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        CachedLink {
+            song: self.song.clone(),
+            downloader: self.downloader.clone(),
+            yt_dlp_path: self.yt_dlp_path,
+            client: self.client.clone(),
+        }
+    }
+}
+
+impl<CS> CachedLink<CS>
+where
+    CS: CacheSaver + Send + Sync + 'static,
+{
+    pub fn new(
+        song: CachedSong,
+        downloader: Downloader<CS>,
+        yt_dlp_path: &'static str,
+        client: Client,
+    ) -> Self {
+        CachedLink {
+            song,
+            downloader,
+            yt_dlp_path,
+            client,
+        }
+    }
+}
+
+#[async_trait]
+impl<CS> Song for CachedLink<CS>
+where
+    CS: CacheSaver + Send + Sync + 'static,
+{
+    fn title(&self) -> &String {
+        self.song.title()
+    }
+
+    fn artist(&self) -> &String {
+        self.song.artist()
+    }
+
+    fn duration(&self) -> Option<u64> {
+        self.song.duration()
+    }
+
+    fn clone_song(&self) -> Box<dyn Song> {
+        Box::new(self.clone())
+    }
+
+    fn get_id(&self) -> &SongId {
+        self.song.get_id()
+    }
+
+    async fn gain(&self) -> Option<f32> {
+        self.song.gain
+    }
+
+    async fn get_input(&self) -> Input {
+        if self.song.is_playable().await {
+            return self.song.get_input().await;
+        }
+        event!(
+            Level::WARN,
+            "Cached file {:?} of {} is unreadable, streaming instead",
+            self.song.path,
+            self.song.id
+        );
+        // The download replaces the cache entry once it is done
+        tokio::spawn(
+            self.downloader
+                .download(self.song.id.clone(), Box::new(self.clone())),
+        );
+        songbird::input::YoutubeDl::new_ytdl_like(
+            self.yt_dlp_path,
+            self.client.clone(),
+            self.song.id.clone(),
+        )
+        .into()
+    }
+}
+
+#[async_trait]
+impl<CS> CacheableSong for CachedLink<CS>
+where
+    CS: CacheSaver + Send + Sync + 'static,
+{
+    type E = String;
+    fn get_path(&self) -> PathBuf {
+        self.song.path.clone()
+    }
+
+    /// Downloads the song again next to the broken file, which is only replaced once the
+    /// download succeeded. The extension can differ from the broken file's
+    async fn cache_song(&self) -> Result<CachedSong, Self::E> {
+        let path = &self.song.path;
+        let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+            return Err(format!("Invalid cache path {:?}", path));
+        };
+        let stem = stem.to_string_lossy();
+        let download_stem = format!("{}.{}", stem, REDOWNLOAD_SUFFIX);
+        YoutubeDl::new(&self.song.id)
+            .youtube_dl_path(self.yt_dlp_path)
+            .output_template(format!("{}/{}.%(ext)s", dir.display(), download_stem))
+            .format("ba")
+            .download_to_async("./")
+            .await
+            .map_err(|e| e.to_string())?;
+        let downloaded = find_file_with_stem(dir, &download_stem)
+            .await
+            .ok_or(format!("Downloaded file of {} not found", self.song.id))?;
+        let new_path = match downloaded.extension() {
+            Some(ext) => dir.join(format!("{}.{}", stem, ext.to_string_lossy())),
+            None => dir.join(stem.as_ref()),
+        };
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(format!("Failed to remove broken file {:?}: {}", path, e));
+            }
+            _ => (),
+        }
+        tokio::fs::rename(&downloaded, &new_path)
+            .await
+            .map_err(|e| format!("Failed to move {:?}: {}", downloaded, e))?;
+        event!(Level::INFO, "Downloaded {} again", self.song.id);
+        Ok(CachedSong {
+            path: new_path,
+            last_played: chrono::Utc::now().timestamp(),
+            gain: None,
+            size: None,
+            ..self.song.clone()
+        })
+    }
+}
+
+/// The file in the dir with the stem, whatever its extension is
+async fn find_file_with_stem(dir: &Path, stem: &str) -> Option<PathBuf> {
+    let mut entries = tokio::fs::read_dir(dir).await.ok()?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.file_stem().is_some_and(|s| s == stem) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs, os::unix::fs::PermissionsExt, sync::Arc, time::Duration};
+
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use crate::{
+        audio_manager::{
+            link_handler::NullLinkHandler,
+            test_utils::{cached_song, sine_wav},
+            AudioManager,
+        },
+        cache_manager::{cache_saver::MemoryCacheSaver, CacheManager, CachedEntity},
+    };
+
+    /// A yt-dlp that copies a wav file to the output template like a download would
+    fn fake_yt_dlp(dir: &Path) -> &'static str {
+        fs::write(dir.join("source.wav"), sine_wav(1, 0.5)).expect("Failed to write song");
+        let yt_dlp = dir.join("yt-dlp");
+        let script = format!(
+            "#!/bin/sh\nwhile [ $# -gt 0 ]; do\n  [ \"$1\" = \"-o\" ] && out=\"$2\"\n  shift\ndone\n\
+             cp {} \"$(echo \"$out\" | sed 's/%(ext)s/wav/')\"\n",
+            dir.join("source.wav").display()
+        );
+        fs::write(&yt_dlp, script).expect("Failed to write fake yt-dlp");
+        fs::set_permissions(&yt_dlp, fs::Permissions::from_mode(0o755))
+            .expect("Failed to set permissions");
+        yt_dlp.display().to_string().leak()
+    }
+
+    #[tokio::test]
+    async fn test_cached_link_downloads_broken_file_again() {
+        let tempdir = temp_dir().join("test_cached_link_downloads_broken_file_again");
+        fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        fs::write(tempdir.join("a.wav"), "not audio").expect("Failed to write file");
+        let yt_dlp = fake_yt_dlp(&tempdir);
+
+        let id = "https://www.youtube.com/watch?v=a".to_string();
+        let cache_manager = Arc::new(RwLock::new(CacheManager::new(MemoryCacheSaver::new())));
+        cache_manager.write().await.add_entry(
+            id.clone(),
+            CachedEntity::Song(cached_song(&id, tempdir.join("a.wav"))),
+        );
+        let mut audio_manager = AudioManager::new(cache_manager.clone(), NullLinkHandler {}, "./")
+            .with_yt_dlp_path(yt_dlp);
+        let song = audio_manager
+            .handle_link(&id)
+            .await
+            .expect("Failed to handle link")
+            .remove(0);
+        let _input = song.get_input().await;
+
+        let mut replaced = None;
+        for _ in 0..100 {
+            if let Some(CachedEntity::Song(song)) = cache_manager.read().await.get_entry(&id) {
+                if song.gain.is_some() {
+                    replaced = Some(song.clone());
+                    break;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let replaced = replaced.expect("Cache entry was not replaced");
+        assert!(replaced.is_playable().await);
+        assert_eq!(
+            replaced.size,
+            fs::metadata(tempdir.join("a.wav")).ok().map(|m| m.len())
+        );
+        fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+
+    #[tokio::test]
+    async fn test_cached_link_download_changes_extension() {
+        let tempdir = temp_dir().join("test_cached_link_download_changes_extension");
+        fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        fs::write(tempdir.join("b.m4a"), "not audio").expect("Failed to write file");
+        let yt_dlp = fake_yt_dlp(&tempdir);
+        let cache_manager = Arc::new(RwLock::new(CacheManager::new(MemoryCacheSaver::new())));
+        let audio_manager = AudioManager::new(cache_manager, NullLinkHandler {}, "./");
+        let song = cached_song("https://www.youtube.com/watch?v=b", tempdir.join("b.m4a"));
+        let link = CachedLink::new(
+            song,
+            audio_manager.downloader.clone(),
+            yt_dlp,
+            Client::new(),
+        );
+
+        let cached = link.cache_song().await.expect("Failed to download");
+        assert_eq!(cached.path, tempdir.join("b.wav"));
+        assert!(cached.is_playable().await);
+        assert!(!tempdir.join("b.m4a").exists());
+        assert!(!tempdir.join("b.redownload.wav").exists());
+        fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::sync::{oneshot, Notify};
+use tracing::{event, Level};
+
+use crate::{
+    cache_manager::{CacheableSong, CachedSong},
+    common::SongId,
+};
+
+const DEFAULT_CONCURRENCY: usize = 3;
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(2);
+
+pub type DownloadResult = Result<CachedSong, String>;
+
+/// Parts of yt-dlp errors that fail the same way on every attempt
+const PERMANENT_ERRORS: [&str; 10] = [
+    "video unavailable",
+    "private video",
+    "video has been removed",
+    "no longer available",
+    "this video is not available",
+    "account associated with this video has been terminated",
+    "unsupported url",
+    "is not a valid url",
+    "http error 404",
+    "http error 410",
+];
+/// yt-dlp exits with 2 on invalid options or links, formatted like `youtube_dl::Error` does
+const INVALID_INPUT_EXIT_CODE: &str = "non-zero exit code: 2,";
+
+/// Whether retrying the download can't help, like for removed videos or unsupported links
+fn is_permanent(error: &str) -> bool {
+    let error = error.to_lowercase();
+    error.contains(INVALID_INPUT_EXIT_CODE) || PERMANENT_ERRORS.iter().any(|e| error.contains(e))
+}
+
+#[derive(Clone, Debug)]
+pub struct DownloadConfig {
+    /// How many songs are downloaded at the same time
+    pub concurrency: usize,
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every failed attempt
+    pub backoff: Duration,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        DownloadConfig {
+            concurrency: DEFAULT_CONCURRENCY,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            backoff: DEFAULT_BACKOFF,
+        }
+    }
+}
+
+struct Job {
+    id: SongId,
+    song: Box<dyn CacheableSong<E = String>>,
+    /// Lowest position of the song in any guild queue
+    priority: usize,
+    order: u64,
+    attempt: u32,
+    done: oneshot::Sender<DownloadResult>,
+}
+
+#[derive(Default)]
+struct Jobs {
+    pending: Vec<Job>,
+    next_order: u64,
+}
+
+struct Inner {
+    config: DownloadConfig,
+    jobs: Mutex<Jobs>,
+    notify: Notify,
+}
+
+impl Inner {
+    fn jobs(&self) -> std::sync::MutexGuard<'_, Jobs> {
+        self.jobs.lock().expect("Download queue lock was poisoned")
+    }
+
+    fn requeue(&self, job: Job) {
+        self.jobs().pending.push(job);
+        self.notify.notify_one();
+    }
+
+    /// Takes the job closest to the head of a queue, songs that aren't queued go in insertion order
+    fn pop(&self) -> Option<Job> {
+        let mut jobs = self.jobs();
+        let index = jobs
+            .pending
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, job)| (job.priority, job.order))
+            .map(|(i, _)| i)?;
+        Some(jobs.pending.remove(index))
+    }
+
+    async fn next(&self) -> Job {
+        loop {
+            if let Some(job) = self.pop() {
+                return job;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    async fn work(self: Arc<Self>) {
+        loop {
+            let mut job = self.next().await;
+            match job.song.cache_song().await {
+                Ok(cached) => {
+                    let _ = job.done.send(Ok(cached));
+                }
+                Err(e) if job.attempt < self.config.max_attempts && !is_permanent(&e) => {
+                    let delay = self.config.backoff * 2u32.pow(job.attempt - 1);
+                    event!(
+                        Level::WARN,
+                        "Failed to download {} (attempt {}), retrying in {:?}: {}",
+                        job.id,
+                        job.attempt,
+                        delay,
+                        e
+                    );
+                    job.attempt += 1;
+                    let inner = self.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        inner.requeue(job);
+                    });
+                }
+                Err(e) => {
+                    event!(Level::ERROR, "Failed to download {}: {}", job.id, e);
+                    let _ = job.done.send(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Songs waiting to be downloaded by a limited number of workers
+pub struct DownloadQueue {
+    inner: Arc<Inner>,
+    started: AtomicBool,
+}
+
+impl DownloadQueue {
+    pub fn new(config: DownloadConfig) -> Self {
+        DownloadQueue {
+            inner: Arc::new(Inner {
+                config,
+                jobs: Mutex::new(Jobs::default()),
+                notify: Notify::new(),
+            }),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    pub fn push(
+        &self,
+        id: SongId,
+        song: Box<dyn CacheableSong<E = String>>,
+    ) -> oneshot::Receiver<DownloadResult> {
+        // The workers are started on first use so the queue can be created outside of a runtime
+        if !self.started.swap(true, Ordering::SeqCst) {
+            for _ in 0..self.inner.config.concurrency.max(1) {
+                tokio::spawn(self.inner.clone().work());
+            }
+        }
+        let (done, receiver) = oneshot::channel();
+        let mut jobs = self.inner.jobs();
+        let order = jobs.next_order;
+        jobs.next_order += 1;
+        jobs.pending.push(Job {
+            id,
+            song,
+            priority: usize::MAX,
+            order,
+            attempt: 1,
+            done,
+        });
+        drop(jobs);
+        self.inner.notify.notify_one();
+        receiver
+    }
+
+    /// Moves the pending downloads of the given songs ahead, in the order they are given
+    pub fn prioritize(&self, ids: &[SongId]) {
+        for job in self.inner.jobs().pending.iter_mut() {
+            if let Some(position) = ids.iter().position(|id| *id == job.id) {
+                job.priority = job.priority.min(position);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_manager::test_utils::FakeSong;
+
+    fn config(concurrency: usize) -> DownloadConfig {
+        DownloadConfig {
+            concurrency,
+            max_attempts: 3,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn test_download_queue_permanent_errors() {
+        assert!(is_permanent(
+            "non-zero exit code: 1, stderr: ERROR: [youtube] a: Private video. Sign in"
+        ));
+        assert!(is_permanent(
+            "non-zero exit code: 1, stderr: ERROR: Unsupported URL: https://example.com"
+        ));
+        assert!(is_permanent(
+            "non-zero exit code: 2, stderr: yt-dlp: error: no such option"
+        ));
+        assert!(!is_permanent(
+            "non-zero exit code: 1, stderr: ERROR: Unable to download webpage: timed out"
+        ));
+        assert!(!is_permanent("io error: Connection reset by peer"));
+    }
+
+    #[tokio::test]
+    async fn test_download_queue_priority() {
+        let queue = DownloadQueue::new(config(1));
+        let log = Arc::new(Mutex::new(vec![]));
+        let song = |id: &str| Box::new(FakeSong::new(id, 0, log.clone()));
+
+        let receivers = ["a", "b", "c", "d"].map(|id| queue.push(id.to_string(), song(id)));
+        queue.prioritize(&["d".to_string(), "b".to_string()]);
+        for receiver in receivers {
+            receiver
+                .await
+                .expect("Download dropped")
+                .expect("Download failed");
+        }
+        assert_eq!(*log.lock().unwrap(), vec!["d", "b", "a", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_download_queue_retries() {
+        let queue = DownloadQueue::new(config(2));
+        let log = Arc::new(Mutex::new(vec![]));
+        let flaky = FakeSong::new("flaky", 2, log.clone());
+        let broken = FakeSong::new("broken", 5, log.clone());
+        let removed = FakeSong::new("removed", 5, log.clone()).with_error(
+            "non-zero exit code: 1, stderr: ERROR: [youtube] removed: Video unavailable",
+        );
+
+        let res = queue.push("flaky".to_string(), Box::new(flaky.clone()));
+        assert!(res.await.expect("Download dropped").is_ok());
+        assert_eq!(flaky.attempts(), 3);
+
+        let res = queue.push("broken".to_string(), Box::new(broken.clone()));
+        assert!(res.await.expect("Download dropped").is_err());
+        assert_eq!(broken.attempts(), 3);
+
+        let res = queue.push("removed".to_string(), Box::new(removed.clone()));
+        assert!(res.await.expect("Download dropped").is_err());
+        assert_eq!(removed.attempts(), 1);
+        assert_eq!(*log.lock().unwrap(), vec!["flaky"]);
+    }
+}
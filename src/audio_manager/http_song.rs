@@ -0,0 +1,328 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::{
+    header::{HeaderMap, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE},
+    Client, RequestBuilder, Response,
+};
+use songbird::input::{HttpRequest, Input};
+
+use crate::{
+    cache_manager::{CacheableSong, CachedSong},
+    common::{Song, SongId},
+};
+
+use super::link_handler::{LinkHandlerResult, LinkHandling};
+
+static HTTP_REGEX: &str = r"^https?:\/\/\S+$";
+static STREAM_TITLE_REGEX: &str = r"StreamTitle='(.*?)';";
+static ICY_METADATA_HEADER: &str = "Icy-MetaData";
+static ICY_NAME_HEADER: &str = "icy-name";
+static ICY_METAINT_HEADER: &str = "icy-metaint";
+const ICY_METADATA_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a stream title is shown before it is read again
+const STREAM_TITLE_REFRESH: Duration = Duration::from_secs(15);
+
+/// The last title read from the stream, shared by the clones of a song
+#[derive(Default)]
+struct StreamTitle {
+    title: Option<String>,
+    updated: Option<Instant>,
+    refreshing: bool,
+}
+
+/// A song streamed directly from an audio file or an internet radio over HTTP
+#[derive(Clone)]
+pub struct HttpSong {
+    id: SongId,
+    title: String,
+    artist: String,
+    live: bool,
+    client: Client,
+    stream_title: Arc<Mutex<StreamTitle>>,
+}
+
+impl HttpSong {
+    async fn send(request: RequestBuilder) -> Result<Response, String> {
+        request
+            .header(ICY_METADATA_HEADER, "1")
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Reads the headers with a HEAD request, servers that don't support it get a GET of the
+    /// first byte, the body is never read
+    async fn probe(client: &Client, url: &str) -> Result<Response, String> {
+        match Self::send(client.head(url)).await {
+            Ok(response) => Ok(response),
+            Err(_) => Self::send(client.get(url).header(RANGE, "bytes=0-0")).await,
+        }
+    }
+
+    pub async fn new(client: Client, url: &str) -> Result<HttpSong, String> {
+        let response = Self::probe(&client, url).await?;
+        let headers = response.headers();
+        let is_icy =
+            headers.contains_key(ICY_NAME_HEADER) || headers.contains_key(ICY_METAINT_HEADER);
+        if !is_icy && !is_audio(headers) {
+            return Err(format!("Not an audio link: {}", url));
+        }
+        let title = match header(headers, ICY_NAME_HEADER) {
+            Some(name) => name,
+            None => response
+                .url()
+                .path_segments()
+                .and_then(|mut s| s.next_back())
+                .filter(|s| !s.is_empty())
+                .unwrap_or(url)
+                .to_string(),
+        };
+        Ok(HttpSong {
+            id: url.to_string(),
+            title,
+            artist: response.url().host_str().unwrap_or("Unknown").to_string(),
+            live: is_icy
+                || !(headers.contains_key(CONTENT_LENGTH) || headers.contains_key(CONTENT_RANGE)),
+            client,
+            stream_title: Default::default(),
+        })
+    }
+
+    /// Returns the last stream title and reads it again in the background once it is stale
+    fn stream_title(&self) -> Option<String> {
+        let mut stream_title = self
+            .stream_title
+            .lock()
+            .expect("Stream title lock poisoned");
+        let stale = stream_title
+            .updated
+            .is_none_or(|updated| updated.elapsed() >= STREAM_TITLE_REFRESH);
+        if stale && !stream_title.refreshing {
+            stream_title.refreshing = true;
+            let song = self.clone();
+            tokio::spawn(async move {
+                let title = tokio::time::timeout(ICY_METADATA_TIMEOUT, song.read_stream_title())
+                    .await
+                    .ok()
+                    .flatten();
+                let mut stream_title = song
+                    .stream_title
+                    .lock()
+                    .expect("Stream title lock poisoned");
+                stream_title.title = title;
+                stream_title.updated = Some(Instant::now());
+                stream_title.refreshing = false;
+            });
+        }
+        stream_title.title.clone()
+    }
+
+    /// Reads the first ICY metadata block of the stream
+    async fn read_stream_title(&self) -> Option<String> {
+        let mut response = Self::send(self.client.get(&self.id)).await.ok()?;
+        let metaint = header(response.headers(), ICY_METAINT_HEADER)?
+            .parse::<usize>()
+            .ok()?;
+        let mut data = vec![];
+        while let Some(chunk) = response.chunk().await.ok()? {
+            data.extend_from_slice(&chunk);
+            if let Some(length) = data.get(metaint) {
+                let end = metaint + 1 + *length as usize * 16;
+                if data.len() >= end {
+                    let metadata = String::from_utf8_lossy(&data[metaint + 1..end]);
+                    return parse_stream_title(&metadata);
+                }
+            }
+        }
+        None
+    }
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn is_audio(headers: &HeaderMap) -> bool {
+    match header(headers, CONTENT_TYPE.as_str()) {
+        Some(content_type) => {
+            content_type.starts_with("audio/") || content_type.starts_with("application/ogg")
+        }
+        None => false,
+    }
+}
+
+fn parse_stream_title(metadata: &str) -> Option<String> {
+    Regex::new(STREAM_TITLE_REGEX)
+        .expect("Pattern was invalid")
+        .captures(metadata)
+        .map(|c| c[1].trim().to_string())
+        .filter(|t| !t.is_empty())
+}
+
+#[async_trait]
+impl Song for HttpSong {
+    fn title(&self) -> &String {
+        &self.title
+    }
+
+    fn artist(&self) -> &String {
+        &self.artist
+    }
+
+    fn duration(&self) -> Option<u64> {
+        None
+    }
+
+    fn is_live(&self) -> bool {
+        self.live
+    }
+
+    async fn now_playing(&self) -> Option<String> {
+        if !self.live {
+            return None;
+        }
+        self.stream_title()
+    }
+
+    fn clone_song(&self) -> Box<dyn Song> {
+        Box::new(self.clone())
+    }
+
+    fn get_id(&self) -> &SongId {
+        &self.id
+    }
+
+    async fn get_input(&self) -> Input {
+        tracing::info!("Getting HTTP input for {}", self.id);
+        HttpRequest::new(self.client.clone(), self.id.clone()).into()
+    }
+}
+
+#[async_trait]
+impl CacheableSong for HttpSong {
+    type E = String;
+    fn get_path(&self) -> PathBuf {
+        PathBuf::new()
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+
+    async fn cache_song(&self) -> Result<CachedSong, Self::E> {
+        Err("HTTP songs are streamed and not cached".to_string())
+    }
+}
+
+/// Handles any http(s) link that points to audio,
+/// it should be registered after the handlers for specific sites
+pub struct HttpLinkHandler {
+    pattern: Regex,
+    client: Client,
+}
+
+impl HttpLinkHandler {
+    pub fn new() -> Self {
+        HttpLinkHandler {
+            pattern: Regex::new(HTTP_REGEX).expect("Pattern was invalid"),
+            client: Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl LinkHandling for HttpLinkHandler {
+    fn claims(&self, link: &str) -> bool {
+        self.pattern.is_match(link)
+    }
+
+    async fn handle_link(&self, link: &str) -> Result<LinkHandlerResult, String> {
+        let song = HttpSong::new(self.client.clone(), link).await?;
+        Ok(LinkHandlerResult::Song(Box::new(song)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_manager::test_utils::serve;
+
+    fn radio_response() -> Vec<u8> {
+        let metadata = b"StreamTitle='Artist - Song';";
+        let blocks = metadata.len().div_ceil(16);
+        let mut res = b"HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nicy-name: Test Radio\r\nicy-metaint: 8\r\nConnection: close\r\n\r\n".to_vec();
+        res.extend_from_slice(&[0; 8]);
+        res.push(blocks as u8);
+        res.extend_from_slice(metadata);
+        res.resize(res.len() + blocks * 16 - metadata.len(), 0);
+        res.extend_from_slice(&[0; 8]);
+        res
+    }
+
+    #[tokio::test]
+    async fn test_http_link_handler_radio() {
+        let url = format!("{}/stream", serve(radio_response()));
+        let handler = HttpLinkHandler::new();
+        assert!(handler.claims(&url));
+        let song = HttpSong::new(Client::new(), &url)
+            .await
+            .expect("Failed to create song");
+        assert_eq!(song.title(), "Test Radio");
+        assert!(song.is_live());
+        assert_eq!(song.duration(), None);
+        // The title is read in the background
+        let mut now_playing = song.now_playing().await;
+        for _ in 0..50 {
+            if now_playing.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            now_playing = song.now_playing().await;
+        }
+        assert_eq!(now_playing, Some("Artist - Song".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_http_link_handler_file() {
+        let url = serve(
+            b"HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nContent-Length: 4\r\nConnection: close\r\n\r\ndata"
+                .to_vec(),
+        );
+        let song = HttpSong::new(Client::new(), &format!("{}/music/song.mp3", url))
+            .await
+            .expect("Failed to create song");
+        assert_eq!(song.title(), "song.mp3");
+        assert!(!song.is_live());
+        assert_eq!(song.now_playing().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_http_link_handler_not_audio() {
+        let url = serve(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 4\r\nConnection: close\r\n\r\ndata"
+                .to_vec(),
+        );
+        assert!(HttpSong::new(Client::new(), &url).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_stream_title() {
+        assert_eq!(
+            parse_stream_title("StreamTitle='Artist - Song';StreamUrl='';"),
+            Some("Artist - Song".to_string())
+        );
+        assert_eq!(parse_stream_title("StreamTitle='';"), None);
+        assert_eq!(parse_stream_title(""), None);
+    }
+}
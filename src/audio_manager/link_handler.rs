@@ -7,12 +7,22 @@ use reqwest::Client;
 use tokio::sync::RwLock;
 
 use crate::{
-    cache_manager::{self, cache_saver::CacheSaver, CacheManager, CacheableSong, CachedEntity},
-    common::{Song, SongId},
+    cache_manager::{cache_saver::CacheSaver, CacheManager, CacheableSong},
+    common::Song,
 };
 
 use super::songs::{YtResult, YtSong};
 static YOUTUBE_REGEX: &str = r"(https?:\/\/)?(www\.)?(m\.)?(music\.)?((youtube)|(youtu\.be)).*";
+static SOUNDCLOUD_REGEX: &str = r"^(https?:\/\/)?(www\.|m\.|on\.)?soundcloud\.com\/.*";
+static BANDCAMP_REGEX: &str = r"^(https?:\/\/)?([\w-]+\.)?bandcamp\.com\/.*";
+static YOUTUBE_SEARCH_PREFIX: &str = "ytsearch";
+pub(crate) static DEFAULT_YT_DLP_PATH: &str = "yt-dlp";
+/// Input with a scheme is always a link, without one the host has to end in a known TLD
+/// so that searches like "ac.dc" are not mistaken for links
+static LINK_REGEX: &str = concat!(
+    r"(?i)^(https?:\/\/\S+|([\w-]+\.)+",
+    r"(com|net|org|io|be|fm|tv|me|co|uk|de|fr|nl|ru|jp|app|dev|gg|ly|to)(\/\S*)?)$"
+);
 
 pub enum LinkHandlerResult {
     Song(Box<dyn CacheableSong<E = String>>),
@@ -38,15 +48,26 @@ where
 }
 
 #[async_trait]
-pub trait LinkHandling {
+pub trait LinkHandling: Send + Sync {
+    /// Whether this handler is able to handle the link
+    fn claims(&self, link: &str) -> bool;
     async fn handle_link(&self, link: &str) -> Result<LinkHandlerResult, String>;
+    /// Searches for songs matching a plain text query,
+    /// the returned songs can be added with `handle_link` using their id
+    async fn search(&self, _query: &str, _limit: usize) -> Result<Vec<Box<dyn Song>>, String> {
+        Err("Search is not supported".to_string())
+    }
 }
 
+/// Handles links supported by yt-dlp that match the handler's pattern
 pub struct StandardLinkHandler<CS>
 where
     CS: CacheSaver + Clone,
 {
+    pattern: Regex,
+    search_prefix: Option<&'static str>,
     path: PathBuf,
+    yt_dlp_path: &'static str,
     yt_template: String,
     client: Client,
     cache_manager: Arc<RwLock<CacheManager<CS>>>,
@@ -56,15 +77,54 @@ impl<CS> StandardLinkHandler<CS>
 where
     CS: CacheSaver + Clone,
 {
-    pub fn new(path: impl ToString, cache_manager: Arc<RwLock<CacheManager<CS>>>) -> Self {
+    pub fn youtube(path: impl ToString, cache_manager: Arc<RwLock<CacheManager<CS>>>) -> Self {
+        Self::new(
+            path,
+            cache_manager,
+            YOUTUBE_REGEX,
+            Some(YOUTUBE_SEARCH_PREFIX),
+        )
+    }
+    pub fn soundcloud(path: impl ToString, cache_manager: Arc<RwLock<CacheManager<CS>>>) -> Self {
+        Self::new(path, cache_manager, SOUNDCLOUD_REGEX, None)
+    }
+    pub fn bandcamp(path: impl ToString, cache_manager: Arc<RwLock<CacheManager<CS>>>) -> Self {
+        Self::new(path, cache_manager, BANDCAMP_REGEX, None)
+    }
+    pub fn new(
+        path: impl ToString,
+        cache_manager: Arc<RwLock<CacheManager<CS>>>,
+        pattern: &str,
+        search_prefix: Option<&'static str>,
+    ) -> Self {
         let p = PathBuf::from(path.to_string());
         Self {
+            pattern: Regex::new(pattern).expect("Pattern was invalid"),
+            search_prefix,
             path: p,
+            yt_dlp_path: DEFAULT_YT_DLP_PATH,
             yt_template: format!("{}/%(id)s.%(ext)s", path.to_string()),
             client: Client::new(),
             cache_manager,
         }
     }
+    /// Streams need the program as a `&'static str`, the path is configured once at startup
+    pub fn with_yt_dlp_path(mut self, yt_dlp_path: &'static str) -> Self {
+        self.yt_dlp_path = yt_dlp_path;
+        self
+    }
+    async fn get_yt_result(&self, link: &str) -> Result<YtResult<CS>, String> {
+        YtSong::new(
+            link,
+            self.yt_dlp_path,
+            self.client.clone(),
+            self.cache_manager.clone(),
+            self.yt_template.clone(),
+            self.path.clone(),
+        )
+        .await
+        .map_err(|e| format!("Error creating song: {:?}", e))
+    }
 }
 
 #[async_trait]
@@ -72,34 +132,128 @@ impl<CS> LinkHandling for StandardLinkHandler<CS>
 where
     CS: CacheSaver + Clone + 'static + Send + Sync,
 {
+    fn claims(&self, link: &str) -> bool {
+        self.pattern.is_match(link)
+    }
+
     async fn handle_link(&self, link: &str) -> Result<LinkHandlerResult, String> {
-        if !is_yt_link(link) {
-            return Err("Not a youtube link".to_string());
+        if !self.claims(link) {
+            return Err("Link not supported".to_string());
         }
-        let s = YtSong::new(
-            link,
-            self.client.clone(),
-            self.cache_manager.clone(),
-            self.yt_template.clone(),
-            self.path.clone(),
-        )
-        .await
-        .map_err(|e| format!("Error creating song: {:?}", e))?;
+        let s = self.get_yt_result(link).await?;
+
+        Ok(s.into())
+    }
 
-        return Ok(s.into());
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<Box<dyn Song>>, String> {
+        let prefix = self.search_prefix.ok_or("Search is not supported")?;
+        let songs = match self
+            .get_yt_result(&format!("{}{}:{}", prefix, limit, query))
+            .await?
+        {
+            YtResult::Song(song) => vec![song],
+            YtResult::Playlist(songs) => songs,
+        };
+        Ok(songs
+            .into_iter()
+            .map(|s| Box::new(s) as Box<dyn Song>)
+            .collect())
     }
 }
 
-fn is_yt_link(link: &str) -> bool {
-    Regex::new(YOUTUBE_REGEX)
+/// Checks whether the input looks like a link rather than a search query
+pub fn is_link(input: &str) -> bool {
+    Regex::new(LINK_REGEX)
         .expect("Pattern was invalid")
-        .is_match(link)
+        .is_match(input.trim())
 }
 
+#[allow(dead_code)]
 pub struct NullLinkHandler {}
 #[async_trait]
 impl LinkHandling for NullLinkHandler {
+    fn claims(&self, _link: &str) -> bool {
+        false
+    }
+
     async fn handle_link(&self, _link: &str) -> Result<LinkHandlerResult, String> {
         Ok(LinkHandlerResult::Playlist(Vec::new()))
     }
+
+    async fn search(&self, _query: &str, _limit: usize) -> Result<Vec<Box<dyn Song>>, String> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs, os::unix::fs::PermissionsExt};
+
+    use crate::cache_manager::cache_saver::MemoryCacheSaver;
+
+    use super::*;
+
+    const SEARCH_OUTPUT: &str = r#"{"_type": "playlist", "id": "test", "entries": [
+        {"id": "a", "title": "First", "uploader": "Artist A", "duration": 61, "url": "https://www.youtube.com/watch?v=a"},
+        {"id": "b", "title": "Second", "uploader": "Artist B", "url": "https://www.youtube.com/watch?v=b"}
+    ]}"#;
+
+    #[tokio::test]
+    async fn test_standard_link_handler_search() {
+        let tempdir = temp_dir().join("test_standard_link_handler_search");
+        fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        let yt_dlp = tempdir.join("yt-dlp");
+        fs::write(
+            &yt_dlp,
+            format!("#!/bin/sh\ncat <<'EOF'\n{}\nEOF\n", SEARCH_OUTPUT),
+        )
+        .expect("Failed to write fake yt-dlp");
+        fs::set_permissions(&yt_dlp, fs::Permissions::from_mode(0o755))
+            .expect("Failed to set permissions");
+        let yt_dlp = yt_dlp.display().to_string().leak();
+
+        let cache_manager = Arc::new(RwLock::new(CacheManager::new(MemoryCacheSaver::new())));
+        let link_handler =
+            StandardLinkHandler::youtube(tempdir.display(), cache_manager).with_yt_dlp_path(yt_dlp);
+        let res = link_handler
+            .search("test", 2)
+            .await
+            .expect("Failed to search");
+
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].title(), "First");
+        assert_eq!(res[0].artist(), "Artist A");
+        assert_eq!(res[0].duration(), Some(61));
+        assert_eq!(res[0].get_id(), "https://www.youtube.com/watch?v=a");
+        assert_eq!(res[1].title(), "Second");
+        fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+
+    #[test]
+    fn test_standard_link_handler_claims() {
+        let cache_manager = Arc::new(RwLock::new(CacheManager::new(MemoryCacheSaver::new())));
+        let youtube = StandardLinkHandler::youtube("./", cache_manager.clone());
+        let soundcloud = StandardLinkHandler::soundcloud("./", cache_manager.clone());
+        let bandcamp = StandardLinkHandler::bandcamp("./", cache_manager);
+        assert!(youtube.claims("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+        assert!(youtube.claims("https://youtu.be/dQw4w9WgXcQ"));
+        assert!(!youtube.claims("https://soundcloud.com/artist/track"));
+        assert!(soundcloud.claims("https://soundcloud.com/artist/track"));
+        assert!(!soundcloud.claims("https://artist.bandcamp.com/track/song"));
+        assert!(bandcamp.claims("https://artist.bandcamp.com/track/song"));
+        assert!(!bandcamp.claims("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+    }
+
+    #[test]
+    fn test_is_link() {
+        assert!(is_link("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+        assert!(is_link("youtu.be/dQw4w9WgXcQ"));
+        assert!(!is_link("never gonna give you up"));
+        assert!(!is_link("rickroll"));
+        assert!(is_link("soundcloud.com/artist/track"));
+        assert!(is_link("http://localhost:8000/stream"));
+        assert!(!is_link("ac.dc"));
+        assert!(!is_link("will.i.am"));
+        assert!(!is_link("mr.brightside"));
+    }
 }
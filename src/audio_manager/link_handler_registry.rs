@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+
+use crate::common::Song;
+
+use super::link_handler::{LinkHandlerResult, LinkHandling};
+
+/// Tries the registered link handlers in order of registration
+#[derive(Default)]
+pub struct LinkHandlerRegistry {
+    handlers: Vec<Box<dyn LinkHandling>>,
+}
+
+impl LinkHandlerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn register(mut self, handler: impl LinkHandling + 'static) -> Self {
+        self.handlers.push(Box::new(handler));
+        self
+    }
+}
+
+#[async_trait]
+impl LinkHandling for LinkHandlerRegistry {
+    fn claims(&self, link: &str) -> bool {
+        self.handlers.iter().any(|h| h.claims(link))
+    }
+
+    async fn handle_link(&self, link: &str) -> Result<LinkHandlerResult, String> {
+        let mut error = format!("No handler found for link: {}", link);
+        for handler in self.handlers.iter().filter(|h| h.claims(link)) {
+            match handler.handle_link(link).await {
+                Ok(res) => return Ok(res),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<Box<dyn Song>>, String> {
+        let mut error = "Search is not supported".to_string();
+        for handler in &self.handlers {
+            match handler.search(query, limit).await {
+                Ok(res) => return Ok(res),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeLinkHandler {
+        prefix: &'static str,
+        result: Result<(), String>,
+    }
+
+    #[async_trait]
+    impl LinkHandling for FakeLinkHandler {
+        fn claims(&self, link: &str) -> bool {
+            link.starts_with(self.prefix)
+        }
+
+        async fn handle_link(&self, _link: &str) -> Result<LinkHandlerResult, String> {
+            self.result
+                .clone()
+                .map(|_| LinkHandlerResult::Playlist(Vec::new()))
+        }
+    }
+
+    fn registry() -> LinkHandlerRegistry {
+        LinkHandlerRegistry::new()
+            .register(FakeLinkHandler {
+                prefix: "a",
+                result: Err("first".to_string()),
+            })
+            .register(FakeLinkHandler {
+                prefix: "b",
+                result: Err("second".to_string()),
+            })
+            .register(FakeLinkHandler {
+                prefix: "a",
+                result: Ok(()),
+            })
+    }
+
+    #[tokio::test]
+    async fn test_link_handler_registry_tries_claiming_handlers_in_order() {
+        let registry = registry();
+        assert!(registry.handle_link("a").await.is_ok());
+        assert_eq!(
+            registry.handle_link("b").await.err(),
+            Some("second".to_string())
+        );
+        assert!(!registry.claims("c"));
+        assert!(registry.handle_link("c").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_link_handler_registry_search_unsupported() {
+        let registry = registry();
+        assert!(registry.search("query", 5).await.is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use songbird::input::Input;
+use tracing::{event, Level};
+
+use crate::{
+    cache_manager::CacheableSong,
+    common::{Song, SongId},
+};
+
+use super::{
+    link_handler::{LinkHandlerResult, LinkHandling},
+    metadata::read_metadata,
+};
+
+static LOCAL_LINK_PREFIX: &str = "local:";
+
+#[derive(Clone, Debug)]
+pub struct LocalSong {
+    id: SongId,
+    path: PathBuf,
+    title: String,
+    artist: String,
+    album: Option<String>,
+    track_number: Option<u32>,
+    duration: Option<u64>,
+}
+
+impl LocalSong {
+    pub fn album(&self) -> Option<&String> {
+        self.album.as_ref()
+    }
+
+    fn read(root: &Path, path: &Path) -> Option<LocalSong> {
+        let metadata = read_metadata(path)?;
+        let relative_path = path.strip_prefix(root).ok()?.to_string_lossy().to_string();
+        Some(LocalSong {
+            id: format!("{}{}", LOCAL_LINK_PREFIX, relative_path),
+            path: path.to_path_buf(),
+            title: match metadata.title {
+                Some(title) => title,
+                None => path.file_stem()?.to_string_lossy().to_string(),
+            },
+            artist: metadata.artist.unwrap_or("Unknown".to_string()),
+            album: metadata.album,
+            track_number: metadata.track_number,
+            duration: metadata.duration,
+        })
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        let contains = |s: &str| s.to_lowercase().contains(query);
+        contains(&self.title)
+            || contains(&self.artist)
+            || self.album.as_deref().is_some_and(contains)
+    }
+}
+
+#[async_trait]
+impl Song for LocalSong {
+    fn title(&self) -> &String {
+        &self.title
+    }
+
+    fn artist(&self) -> &String {
+        &self.artist
+    }
+
+    fn duration(&self) -> Option<u64> {
+        self.duration
+    }
+
+    fn clone_song(&self) -> Box<dyn Song> {
+        Box::new(self.clone())
+    }
+
+    fn get_id(&self) -> &SongId {
+        &self.id
+    }
+
+    async fn get_input(&self) -> Input {
+        tracing::info!(
+            "Getting local input for {} with path: {:?}",
+            self.id,
+            self.path
+        );
+        songbird::input::File::new(self.path.clone()).into()
+    }
+}
+
+impl CacheableSong for LocalSong {
+    type E = String;
+    fn get_path(&self) -> PathBuf {
+        self.path.clone()
+    }
+}
+
+/// Index of the audio files in a local directory
+pub struct LocalLibrary {
+    songs: Vec<LocalSong>,
+}
+
+impl LocalLibrary {
+    pub fn new(songs: Vec<LocalSong>) -> Self {
+        LocalLibrary { songs }
+    }
+
+    /// Recursively indexes all audio files in `root` that symphonia is able to read
+    pub fn index(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref();
+        let mut songs = vec![];
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    event!(Level::ERROR, "Failed to read library dir {:?}: {}", dir, e);
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if let Some(song) = LocalSong::read(root, &path) {
+                    songs.push(song);
+                }
+            }
+        }
+        songs.sort_by(|a, b| a.id.cmp(&b.id));
+        event!(
+            Level::INFO,
+            "Indexed {} local songs in {:?}",
+            songs.len(),
+            root
+        );
+        LocalLibrary::new(songs)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&LocalSong> {
+        self.songs.iter().find(|s| s.id == id)
+    }
+
+    /// Finds songs whose title, artist or album contain the query
+    pub fn search(&self, query: &str) -> Vec<&LocalSong> {
+        let query = query.trim().to_lowercase();
+        self.songs.iter().filter(|s| s.matches(&query)).collect()
+    }
+
+    /// Finds the songs of an album ordered by track number,
+    /// exact album name matches take precedence over partial ones
+    pub fn album(&self, name: &str) -> Vec<&LocalSong> {
+        let name = name.trim().to_lowercase();
+        let album_name = |s: &&LocalSong| s.album.as_ref().map(|a| a.to_lowercase());
+        let mut songs = self
+            .songs
+            .iter()
+            .filter(|s| album_name(s).as_deref() == Some(name.as_str()))
+            .collect::<Vec<_>>();
+        if songs.is_empty() {
+            songs = self
+                .songs
+                .iter()
+                .filter(|s| album_name(s).is_some_and(|a| a.contains(&name)))
+                .collect();
+        }
+        songs.sort_by_key(|s| (s.album.clone(), s.track_number, s.id.clone()));
+        songs
+    }
+}
+
+pub struct LocalFileLinkHandler {
+    library: Arc<LocalLibrary>,
+}
+
+impl LocalFileLinkHandler {
+    pub fn new(library: Arc<LocalLibrary>) -> Self {
+        LocalFileLinkHandler { library }
+    }
+}
+
+#[async_trait]
+impl LinkHandling for LocalFileLinkHandler {
+    fn claims(&self, link: &str) -> bool {
+        link.starts_with(LOCAL_LINK_PREFIX)
+    }
+
+    async fn handle_link(&self, link: &str) -> Result<LinkHandlerResult, String> {
+        let song = self
+            .library
+            .get(link)
+            .ok_or(format!("Local song not found: {}", link))?;
+        Ok(LinkHandlerResult::Song(Box::new(song.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs};
+
+    use super::*;
+    use crate::audio_manager::test_utils::test_wav;
+
+    fn song(title: &str, album: Option<&str>, track_number: Option<u32>) -> LocalSong {
+        LocalSong {
+            id: format!("{}{}", LOCAL_LINK_PREFIX, title),
+            path: PathBuf::from(title),
+            title: title.to_string(),
+            artist: "Artist".to_string(),
+            album: album.map(|a| a.to_string()),
+            track_number,
+            duration: None,
+        }
+    }
+
+    #[test]
+    fn test_local_library_index() {
+        let tempdir = temp_dir().join("test_local_library_index");
+        fs::create_dir_all(tempdir.join("album")).expect("Failed to create temp dir");
+        fs::write(tempdir.join("album/song.wav"), test_wav(2)).expect("Failed to write song");
+        fs::write(tempdir.join("cover.txt"), "not audio").expect("Failed to write file");
+
+        let library = LocalLibrary::index(&tempdir);
+        let songs = library.search("song");
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs[0].title(), "song");
+        assert_eq!(songs[0].duration(), Some(2));
+        assert_eq!(songs[0].get_id(), "local:album/song.wav");
+        assert!(library.get("local:cover.txt").is_none());
+        fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+
+    #[test]
+    fn test_local_library_album() {
+        let library = LocalLibrary::new(vec![
+            song("b", Some("Album"), Some(2)),
+            song("a", Some("Album"), Some(1)),
+            song("c", Some("Album Deluxe"), Some(1)),
+            song("d", None, None),
+        ]);
+        let titles =
+            |songs: Vec<&LocalSong>| songs.iter().map(|s| s.title().clone()).collect::<Vec<_>>();
+        assert_eq!(titles(library.album("album")), vec!["a", "b"]);
+        assert_eq!(titles(library.album("deluxe")), vec!["c"]);
+        assert!(library.album("missing").is_empty());
+        assert_eq!(titles(library.search("DELUXE")), vec!["c"]);
+    }
+}
@@ -0,0 +1,210 @@
+use std::{f64::consts::PI, fs::File, path::Path};
+
+use songbird::input::codecs::{CODEC_REGISTRY, PROBE};
+use symphonia::core::{
+    audio::SampleBuffer, codecs::DecoderOptions, errors::Error, formats::FormatOptions,
+    io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+
+/// Loudness every song is normalized to, close to what streaming services use
+const TARGET_LOUDNESS: f64 = -14.0;
+const MIN_GAIN: f32 = 0.1;
+/// Quiet songs are only boosted a little to avoid clipping
+const MAX_GAIN: f32 = 2.0;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+/// Blocks are 400ms long and overlap by 75%, so they are made of four 100ms steps
+const BLOCK_STEPS: usize = 4;
+const STEPS_PER_SECOND: u32 = 10;
+
+/// Second order IIR filter in direct form I
+#[derive(Clone)]
+pub(crate) struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    pub(crate) fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    pub(crate) fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[1] * self.y[0]
+            - self.a[2] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// K-weighting filter of ITU-R BS.1770, a high shelf followed by a high pass
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let k = (PI * 1681.974450955533 / sample_rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    let k = (PI * 38.13547087602444 / sample_rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+    [shelf, high_pass]
+}
+
+fn loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// Computes the integrated loudness from the energy of the 100ms steps
+fn integrated_loudness(steps: &[f64]) -> Option<f64> {
+    let blocks = steps
+        .windows(BLOCK_STEPS)
+        .map(|w| w.iter().sum::<f64>() / BLOCK_STEPS as f64)
+        .filter(|e| loudness(*e) > ABSOLUTE_GATE)
+        .collect::<Vec<_>>();
+    if blocks.is_empty() {
+        return None;
+    }
+    let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+    let gate = loudness(mean(&blocks)) + RELATIVE_GATE;
+    let gated = blocks
+        .into_iter()
+        .filter(|e| loudness(*e) > gate)
+        .collect::<Vec<_>>();
+    if gated.is_empty() {
+        return None;
+    }
+    Some(loudness(mean(&gated)))
+}
+
+/// Decodes the file and measures its integrated loudness in LUFS,
+/// returns `None` for files that can't be decoded or are silent
+pub fn measure_loudness(path: &Path) -> Option<f64> {
+    let file = File::open(path).ok()?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let mut format = PROBE
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()?
+        .format;
+    let track = format.default_track()?;
+    let track_id = track.id;
+    let sample_rate = track.codec_params.sample_rate?;
+    let mut decoder = CODEC_REGISTRY
+        .make(&track.codec_params, &DecoderOptions::default())
+        .ok()?;
+
+    let step_length = (sample_rate / STEPS_PER_SECOND) as usize;
+    let mut filters: Vec<[Biquad; 2]> = vec![];
+    let mut steps = vec![];
+    let mut energy = 0.0;
+    let mut frames = 0;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(_)) => break,
+            Err(_) => return None,
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(Error::DecodeError(_)) => continue,
+            Err(_) => return None,
+        };
+        let channels = decoded.spec().channels.count();
+        if filters.len() != channels {
+            filters = vec![k_weighting(sample_rate as f64); channels];
+        }
+        let buffer = match &mut buffer {
+            Some(buffer) if buffer.capacity() >= decoded.capacity() * channels => buffer,
+            _ => buffer.insert(SampleBuffer::new(
+                decoded.capacity() as u64,
+                *decoded.spec(),
+            )),
+        };
+        buffer.copy_interleaved_ref(decoded);
+        for frame in buffer.samples().chunks(channels) {
+            for (sample, filter) in frame.iter().zip(filters.iter_mut()) {
+                let filtered = filter
+                    .iter_mut()
+                    .fold(*sample as f64, |s, biquad| biquad.process(s));
+                energy += filtered * filtered;
+            }
+            frames += 1;
+            if frames == step_length {
+                steps.push(energy / step_length as f64);
+                energy = 0.0;
+                frames = 0;
+            }
+        }
+    }
+    integrated_loudness(&steps)
+}
+
+/// Volume multiplier that brings a song of the given loudness to the target loudness
+pub fn normalization_gain(loudness: f64) -> f32 {
+    let gain = 10f64.powf((TARGET_LOUDNESS - loudness) / 20.0) as f32;
+    gain.clamp(MIN_GAIN, MAX_GAIN)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs};
+
+    use super::*;
+    use crate::audio_manager::test_utils::{sine_wav, test_wav};
+
+    #[test]
+    fn test_measure_loudness() {
+        let tempdir = temp_dir().join("test_measure_loudness");
+        fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        fs::write(tempdir.join("sine.wav"), sine_wav(3, 0.5)).expect("Failed to write song");
+        fs::write(tempdir.join("silent.wav"), test_wav(3)).expect("Failed to write song");
+
+        // A 1kHz sine at -6dBFS on one channel measures about -9 LUFS
+        let loudness = measure_loudness(&tempdir.join("sine.wav")).expect("Failed to measure");
+        assert!((loudness + 9.03).abs() < 0.3, "{}", loudness);
+        assert_eq!(measure_loudness(&tempdir.join("silent.wav")), None);
+        assert_eq!(measure_loudness(&tempdir.join("missing.wav")), None);
+        fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+
+    #[test]
+    fn test_normalization_gain() {
+        assert_eq!(normalization_gain(TARGET_LOUDNESS), 1.0);
+        assert!((normalization_gain(-8.0) - 0.5).abs() < 0.01);
+        assert_eq!(normalization_gain(-60.0), MAX_GAIN);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{fs::File, path::Path};
+
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey},
+    probe::Hint,
+};
+
+/// Tags and duration of an audio file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AudioMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub duration: Option<u64>,
+}
+
+impl AudioMetadata {
+    fn read_tags(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => self.title = Some(tag.value.to_string()),
+                Some(StandardTagKey::Artist) => self.artist = Some(tag.value.to_string()),
+                Some(StandardTagKey::Album) => self.album = Some(tag.value.to_string()),
+                Some(StandardTagKey::TrackNumber) => {
+                    // Track numbers can be stored as "3/12"
+                    self.track_number = tag
+                        .value
+                        .to_string()
+                        .split('/')
+                        .next()
+                        .and_then(|n| n.trim().parse().ok())
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Reads the metadata of an audio file with symphonia,
+/// returns `None` if the file is not a supported audio file
+pub fn read_metadata(path: &Path) -> Option<AudioMetadata> {
+    let file = File::open(path).ok()?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()?;
+
+    let mut metadata = AudioMetadata::default();
+    if let Some(probed_metadata) = probed.metadata.get() {
+        if let Some(revision) = probed_metadata.current() {
+            metadata.read_tags(revision);
+        }
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        metadata.read_tags(revision);
+    }
+    metadata.duration = probed.format.default_track().and_then(|track| {
+        let params = &track.codec_params;
+        let time = params.time_base?.calc_time(params.n_frames?);
+        Some(time.seconds)
+    });
+    Some(metadata)
+}
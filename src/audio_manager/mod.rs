@@ -1,19 +1,57 @@
+mod cached_link;
+mod download_queue;
+mod http_song;
 mod link_handler;
+mod link_handler_registry;
+mod local_files;
+mod loudness;
+mod metadata;
 mod songs;
+#[cfg(test)]
+pub(crate) mod test_utils;
 
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
-use tokio::sync::RwLock;
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use reqwest::Client;
+use tokio::{io::AsyncWriteExt, sync::RwLock};
 
 use crate::{
     audio_manager::link_handler::LinkHandlerResult,
-    cache_manager::{cache_saver::CacheSaver, CacheManager, CacheableSong, CachedEntity},
+    cache_manager::{
+        cache_saver::CacheSaver, CacheManager, CacheableSong, CachedEntity, CachedSong,
+    },
     common::{Song, SongId},
 };
 
-use self::link_handler::LinkHandling;
+use self::{
+    cached_link::CachedLink,
+    download_queue::DownloadQueue,
+    link_handler::{LinkHandling, DEFAULT_YT_DLP_PATH},
+    loudness::{measure_loudness, normalization_gain},
+};
+
+static ATTACHMENT_ID_PREFIX: &str = "attachment:";
+/// Larger attachments aren't downloaded
+const MAX_ATTACHMENT_SIZE: u64 = 100 * 1024 * 1024;
 
-pub use self::link_handler::StandardLinkHandler;
+/// A download that can be awaited by everyone who requested the song
+type Download = Shared<BoxFuture<'static, Result<CachedSong, String>>>;
+
+pub use self::download_queue::DownloadConfig;
+pub use self::http_song::HttpLinkHandler;
+pub use self::link_handler::{is_link, StandardLinkHandler};
+pub use self::link_handler_registry::LinkHandlerRegistry;
+pub use self::local_files::{LocalFileLinkHandler, LocalLibrary};
+pub(crate) use self::loudness::Biquad;
+pub use self::metadata::read_metadata;
 pub struct AudioManager<CS, LH>
 where
     CS: CacheSaver + Send + Sync,
@@ -21,6 +59,49 @@ where
 {
     pub cache_manager_instance: Arc<RwLock<CacheManager<CS>>>,
     pub link_handler: LH,
+    cache_dir: PathBuf,
+    client: Client,
+    downloader: Downloader<CS>,
+    yt_dlp_path: &'static str,
+}
+
+/// Songs of a link, the ones that aren't cached yet are only downloaded by `AudioManager::cache`
+#[derive(Default)]
+pub struct ResolvedLink {
+    pub songs: Vec<Box<dyn Song>>,
+    /// Songs to download, keyed by the id of their cache entry
+    uncached: Vec<(SongId, Box<dyn CacheableSong<E = String>>)>,
+}
+
+impl ResolvedLink {
+    /// Adds the songs of another link after these
+    pub fn append(&mut self, other: ResolvedLink) {
+        self.songs.extend(other.songs);
+        self.uncached.extend(other.uncached);
+    }
+}
+
+/// Downloads songs through the download queue, shared with the songs that are downloaded again
+pub(crate) struct Downloader<CS>
+where
+    CS: CacheSaver + Send + Sync,
+{
+    cache_manager: Arc<RwLock<CacheManager<CS>>>,
+    downloads: Arc<Mutex<HashMap<SongId, Download>>>,
+    download_queue: Arc<DownloadQueue>,
+}
+
+impl<CS> Clone for Downloader<CS>
+where
+    CS: CacheSaver + Send + Sync,
+{
+    fn clone(&self) -> Self {
+        Downloader {
+            cache_manager: self.cache_manager.clone(),
+            downloads: self.downloads.clone(),
+            download_queue: self.download_queue.clone(),
+        }
+    }
 }
 
 impl<CS, LH> AudioManager<CS, LH>
@@ -28,53 +109,180 @@ where
     CS: CacheSaver + Send + Sync + 'static,
     LH: LinkHandling,
 {
-    pub fn new(cache_manager: Arc<RwLock<CacheManager<CS>>>, link_handler: LH) -> Self {
+    pub fn new(
+        cache_manager: Arc<RwLock<CacheManager<CS>>>,
+        link_handler: LH,
+        cache_dir: impl Into<PathBuf>,
+    ) -> Self {
         Self {
-            cache_manager_instance: cache_manager,
+            cache_manager_instance: cache_manager.clone(),
             link_handler,
+            cache_dir: cache_dir.into(),
+            client: Client::new(),
+            downloader: Downloader {
+                cache_manager,
+                downloads: Arc::new(Mutex::new(HashMap::new())),
+                download_queue: Arc::new(DownloadQueue::new(DownloadConfig::default())),
+            },
+            yt_dlp_path: DEFAULT_YT_DLP_PATH,
         }
     }
+    pub fn with_download_config(mut self, config: DownloadConfig) -> Self {
+        self.downloader.download_queue = Arc::new(DownloadQueue::new(config));
+        self
+    }
+    /// Used to download and stream cached songs again when their file is broken
+    pub fn with_yt_dlp_path(mut self, yt_dlp_path: &'static str) -> Self {
+        self.yt_dlp_path = yt_dlp_path;
+        self
+    }
+    /// Downloads the songs that are earlier in the list first
+    pub fn prioritize(&self, ids: &[SongId]) {
+        self.downloader.download_queue.prioritize(ids);
+    }
+    /// Gets the songs of the link and downloads the ones that aren't cached yet
     pub async fn handle_link(&mut self, link: &str) -> Result<Vec<Box<dyn Song>>, String> {
+        let resolved = self.resolve_link(link).await?;
+        let songs = resolved.songs.iter().map(|s| s.clone_song()).collect();
+        let ids = resolved
+            .uncached
+            .iter()
+            .map(|(_, song)| song.get_id().clone())
+            .collect::<Vec<_>>();
+        self.cache(resolved, &ids);
+        Ok(songs)
+    }
+
+    /// Gets the songs of the link without downloading them, see `cache`
+    pub async fn resolve_link(&mut self, link: &str) -> Result<ResolvedLink, String> {
         // Read from cache
         let cache_entry = {
             let read = self.cache_manager_instance.read().await;
             read.get_entry(link).cloned()
         };
         if let Some(cached) = cache_entry {
-            return self.handle_cached(cached).await;
+            return Ok(ResolvedLink {
+                songs: self.handle_cached(cached).await?,
+                uncached: vec![],
+            });
         }
 
         let lh_result = self.link_handler.handle_link(link).await?;
         match lh_result {
-            LinkHandlerResult::Song(song) => {
-                let res = song.clone_song();
-                Self::cache_song(self.cache_manager_instance.clone(), link.to_string(), song);
-                Ok(vec![res])
-            }
+            LinkHandlerResult::Song(song) => Ok(ResolvedLink {
+                songs: vec![song.clone_song()],
+                uncached: vec![(link.to_string(), song)],
+            }),
             LinkHandlerResult::Playlist(songs) => {
-                let mut res = vec![];
                 let ids = songs.iter().map(|s| s.get_id().to_string()).collect();
                 self.cache_manager_instance
                     .write()
                     .await
                     .add_entry(link.to_string(), CachedEntity::Playlist(ids));
-                for song in songs {
-                    let s = song.clone_song();
-                    Self::cache_song(
-                        self.cache_manager_instance.clone(),
-                        song.get_id().to_string(),
-                        song,
-                    );
-                    res.push(s);
-                }
-                Ok(res)
+                Ok(ResolvedLink {
+                    songs: songs.iter().map(|s| s.clone_song()).collect(),
+                    uncached: songs
+                        .into_iter()
+                        .map(|song| (song.get_id().to_string(), song))
+                        .collect(),
+                })
             }
         }
     }
 
+    /// Downloads the songs of the resolved link that have one of the ids
+    pub fn cache(&self, resolved: ResolvedLink, ids: &[SongId]) {
+        for (id, song) in resolved.uncached {
+            if ids.contains(song.get_id()) {
+                self.cache_song(id, song);
+            }
+        }
+    }
+
+    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<Box<dyn Song>>, String> {
+        self.link_handler.search(query, limit).await
+    }
+
+    /// Downloads an uploaded audio file into the cache dir and caches it as a song,
+    /// files that aren't audio or are too large are rejected before they are downloaded
+    pub async fn handle_attachment(
+        &mut self,
+        id: u64,
+        url: &str,
+        file_name: &str,
+        content_type: Option<&str>,
+        size: u64,
+    ) -> Result<Box<dyn Song>, String> {
+        let song_id = format!("{}{}", ATTACHMENT_ID_PREFIX, id);
+        if let Some(CachedEntity::Song(song)) =
+            self.cache_manager_instance.read().await.get_entry(&song_id)
+        {
+            return Ok(song.clone_song());
+        }
+        if !content_type.is_some_and(|t| t.starts_with("audio/")) {
+            return Err("Not an audio file".to_string());
+        }
+        if size > MAX_ATTACHMENT_SIZE {
+            return Err(format!(
+                "Audio files can be at most {} MB",
+                MAX_ATTACHMENT_SIZE / 1024 / 1024
+            ));
+        }
+
+        let file_name = Path::new(file_name);
+        let cached_name = match file_name.extension() {
+            Some(ext) => format!("attachment_{}.{}", id, ext.to_string_lossy()),
+            None => format!("attachment_{}", id),
+        };
+        let path = self.cache_dir.join(cached_name);
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+        if let Err(e) = save_body(response, &path).await {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(e);
+        }
+
+        let metadata_path = path.clone();
+        let metadata = tokio::task::spawn_blocking(move || read_metadata(&metadata_path))
+            .await
+            .map_err(|e| e.to_string())?;
+        let Some(metadata) = metadata else {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err("Not an audio file".to_string());
+        };
+        let title = match metadata.title {
+            Some(title) => title,
+            None => file_name
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or("Unknown".to_string()),
+        };
+        let song = CachedSong {
+            id: song_id.clone(),
+            path,
+            title,
+            artist: metadata.artist.unwrap_or("Unknown".to_string()),
+            duration: metadata.duration,
+            last_played: chrono::Utc::now().timestamp(),
+            gain: None,
+            size: None,
+        };
+        let song = analyze(song).await;
+        self.cache_manager_instance
+            .write()
+            .await
+            .add_entry(song_id, CachedEntity::Song(song.clone()));
+        Ok(song.clone_song())
+    }
+
     async fn handle_cached(&self, cached: CachedEntity) -> Result<Vec<Box<dyn Song>>, String> {
         match cached {
-            CachedEntity::Song(song) => return Ok(vec![song.clone_song()]),
+            CachedEntity::Song(song) => Ok(vec![self.cached_song(song)]),
             CachedEntity::Playlist(song_ids) => {
                 let mut res = vec![];
                 for id in song_ids {
@@ -82,17 +290,17 @@ where
                         res.push(song);
                     }
                 }
-                return Ok(res);
+                Ok(res)
             }
         }
     }
 
     async fn handle_song(&self, song: &SongId) -> Result<Box<dyn Song>, String> {
-        if let Some(CachedEntity::Song(song)) =
-            self.cache_manager_instance.read().await.get_entry(song)
-        {
-            return Ok(song.clone_song());
+        let cache_manager = self.cache_manager_instance.read().await;
+        if let Some(CachedEntity::Song(song)) = cache_manager.get_entry(song) {
+            return Ok(self.cached_song(song.clone()));
         }
+        drop(cache_manager);
         match self.link_handler.handle_link(song).await? {
             LinkHandlerResult::Song(song) => Ok(song.clone_song()),
             LinkHandlerResult::Playlist(_songs) => {
@@ -101,21 +309,218 @@ where
         }
     }
 
-    fn cache_song(
-        cache_manager_instance: Arc<RwLock<CacheManager<CS>>>,
-        id: SongId,
-        song: Box<dyn CacheableSong<E = String>>,
-    ) {
+    /// Songs of links can be downloaded again if their file breaks
+    fn cached_song(&self, song: CachedSong) -> Box<dyn Song> {
+        if !is_link(&song.id) {
+            return Box::new(song);
+        }
+        Box::new(CachedLink::new(
+            song,
+            self.downloader.clone(),
+            self.yt_dlp_path,
+            self.client.clone(),
+        ))
+    }
+
+    fn cache_song(&self, id: SongId, song: Box<dyn CacheableSong<E = String>>) {
+        if !song.is_cacheable() {
+            return;
+        }
         // Spawn a thead to cache the song
-        tokio::spawn(async move {
-            let Ok(cached) = song.cache_song().await else {
-                return;
-            };
+        tokio::spawn(self.download(id, song));
+    }
 
-            cache_manager_instance
-                .write()
+    fn download(&self, id: SongId, song: Box<dyn CacheableSong<E = String>>) -> Download {
+        self.downloader.download(id, song)
+    }
+}
+
+impl<CS> Downloader<CS>
+where
+    CS: CacheSaver + Send + Sync + 'static,
+{
+    /// Starts downloading the song, or returns the download that is already in progress for it.
+    /// The finished song replaces its cache entry
+    fn download(&self, id: SongId, song: Box<dyn CacheableSong<E = String>>) -> Download {
+        let mut downloads = self.downloads.lock().expect("Downloads lock was poisoned");
+        if let Some(download) = downloads.get(&id) {
+            return download.clone();
+        }
+        let cache_manager_instance = self.cache_manager.clone();
+        let in_flight = self.downloads.clone();
+        let song_id = id.clone();
+        let receiver = self.download_queue.push(id.clone(), song);
+        let download = async move {
+            let res = receiver
                 .await
-                .add_entry(id, CachedEntity::Song(cached));
-        });
+                .unwrap_or_else(|_| Err("Download was cancelled".to_string()));
+            let res = match res {
+                Ok(cached) if cached.gain.is_none() => Ok(analyze(cached).await),
+                res => res,
+            };
+            if let Ok(cached) = &res {
+                cache_manager_instance
+                    .write()
+                    .await
+                    .add_entry(song_id.clone(), CachedEntity::Song(cached.clone()));
+            }
+            in_flight
+                .lock()
+                .expect("Downloads lock was poisoned")
+                .remove(&song_id);
+            res
+        }
+        .boxed()
+        .shared();
+        downloads.insert(id, download.clone());
+        download
+    }
+}
+
+/// Writes the body to the file as it arrives, fails once it is larger than attachments can be
+async fn save_body(mut response: reqwest::Response, path: &Path) -> Result<(), String> {
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut size = 0;
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        size += chunk.len() as u64;
+        if size > MAX_ATTACHMENT_SIZE {
+            return Err("Audio file is too large".to_string());
+        }
+        file.write_all(&chunk).await.map_err(|e| e.to_string())?;
+    }
+    file.flush().await.map_err(|e| e.to_string())
+}
+
+/// Measures the loudness of a cached song to normalize its volume
+async fn analyze(mut song: CachedSong) -> CachedSong {
+    let path = song.path.clone();
+    let loudness = tokio::task::spawn_blocking(move || measure_loudness(&path))
+        .await
+        .ok()
+        .flatten();
+    song.gain = loudness.map(normalization_gain);
+    song
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs};
+
+    use crate::cache_manager::cache_saver::MemoryCacheSaver;
+
+    use super::{link_handler::NullLinkHandler, test_utils::*, *};
+
+    fn audio_response(body: &[u8]) -> Vec<u8> {
+        let mut res = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: audio/wav\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        res.extend_from_slice(body);
+        res
+    }
+
+    #[tokio::test]
+    async fn test_audio_manager_handle_attachment() {
+        let tempdir = temp_dir().join("test_audio_manager_handle_attachment");
+        fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        let cache_manager = Arc::new(RwLock::new(CacheManager::new(MemoryCacheSaver::new())));
+        let mut audio_manager =
+            AudioManager::new(cache_manager.clone(), NullLinkHandler {}, &tempdir);
+
+        let wav = test_wav(3);
+        let url = serve(audio_response(&wav));
+        let song = audio_manager
+            .handle_attachment(42, &url, "my song.wav", Some("audio/wav"), wav.len() as u64)
+            .await
+            .expect("Failed to handle attachment");
+        assert_eq!(song.title(), "my song");
+        assert_eq!(song.duration(), Some(3));
+        assert_eq!(song.get_id(), "attachment:42");
+        assert!(tempdir.join("attachment_42.wav").exists());
+        assert!(cache_manager
+            .read()
+            .await
+            .get_entry("attachment:42")
+            .is_some());
+
+        let url = serve(audio_response(b"not audio"));
+        assert!(audio_manager
+            .handle_attachment(43, &url, "file.txt", Some("audio/wav"), 9)
+            .await
+            .is_err());
+        assert!(!tempdir.join("attachment_43.txt").exists());
+
+        // Rejected before they are downloaded
+        assert!(audio_manager
+            .handle_attachment(44, &url, "file.txt", Some("text/plain"), 9)
+            .await
+            .is_err());
+        assert!(audio_manager
+            .handle_attachment(45, &url, "file.wav", None, 9)
+            .await
+            .is_err());
+        assert!(audio_manager
+            .handle_attachment(
+                46,
+                &url,
+                "big.wav",
+                Some("audio/wav"),
+                MAX_ATTACHMENT_SIZE + 1
+            )
+            .await
+            .is_err());
+        assert!(!tempdir.join("attachment_44.txt").exists());
+        assert!(!tempdir.join("attachment_45.wav").exists());
+        assert!(!tempdir.join("attachment_46.wav").exists());
+        fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+
+    #[tokio::test]
+    async fn test_audio_manager_deduplicates_downloads() {
+        let cache_manager = Arc::new(RwLock::new(CacheManager::new(MemoryCacheSaver::new())));
+        let audio_manager = AudioManager::new(cache_manager.clone(), NullLinkHandler {}, "./");
+        let log = Arc::new(Mutex::new(vec![]));
+        let song = FakeSong::new("song", 0, log.clone());
+
+        let first = audio_manager.download("song".to_string(), Box::new(song.clone()));
+        let second = audio_manager.download("song".to_string(), Box::new(song.clone()));
+        let (first, second) = tokio::join!(first, second);
+        assert!(first.is_ok() && second.is_ok());
+        assert_eq!(song.attempts(), 1);
+        assert!(cache_manager.read().await.get_entry("song").is_some());
+
+        audio_manager
+            .download("song".to_string(), Box::new(song.clone()))
+            .await
+            .expect("Failed to download");
+        assert_eq!(song.attempts(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_audio_manager_caches_accepted_songs() {
+        let cache_manager = Arc::new(RwLock::new(CacheManager::new(MemoryCacheSaver::new())));
+        let audio_manager = AudioManager::new(cache_manager.clone(), NullLinkHandler {}, "./");
+        let log = Arc::new(Mutex::new(vec![]));
+        let songs = ["a", "b"].map(|id| FakeSong::new(id, 0, log.clone()));
+        let resolved = ResolvedLink {
+            songs: songs.iter().map(|s| s.clone_song()).collect(),
+            uncached: songs
+                .iter()
+                .map(|s| (s.get_id().clone(), Box::new(s.clone()) as _))
+                .collect(),
+        };
+
+        audio_manager.cache(resolved, &["b".to_string()]);
+        // Waits for the download that is in progress
+        audio_manager
+            .download("b".to_string(), Box::new(songs[1].clone()))
+            .await
+            .expect("Failed to download");
+        assert_eq!(songs[0].attempts(), 0);
+        assert_eq!(songs[1].attempts(), 1);
+        assert!(cache_manager.read().await.get_entry("a").is_none());
     }
 }
@@ -32,6 +32,7 @@ where
     duration: Option<u64>,
     extension: Option<String>,
     yt_id: String,
+    yt_dlp_path: &'static str,
     client: reqwest::Client,
     cache_manager: Arc<RwLock<CacheManager<CS>>>,
     output_template: String,
@@ -40,7 +41,7 @@ where
 
 #[derive(Debug)]
 pub enum YtSongError {
-    YoutubeDlError(youtube_dl::Error),
+    YoutubeDlError(#[allow(dead_code)] youtube_dl::Error),
     UnknownError,
     LinkNotFound,
 }
@@ -55,27 +56,44 @@ impl<CS> YtSong<CS>
 where
     CS: CacheSaver + Clone,
 {
+    #[allow(clippy::new_ret_no_self)]
     pub async fn new(
         link: &str,
+        yt_dlp_path: &'static str,
         client: reqwest::Client,
         cache_manager: Arc<RwLock<CacheManager<CS>>>,
         output_template: String,
         base_path: PathBuf,
     ) -> Result<YtResult<CS>, YtSongError> {
         let yt_result = YoutubeDl::new(link.to_string())
+            .youtube_dl_path(yt_dlp_path)
             .flat_playlist(true)
             .run_async()
             .await?;
 
         if let Some(sv) = yt_result.clone().into_single_video() {
-            return Self::from_sv(sv, client, cache_manager, output_template, base_path);
+            return Self::from_sv(
+                sv,
+                yt_dlp_path,
+                client,
+                cache_manager,
+                output_template,
+                base_path,
+            );
         }
 
         if let Some(pl) = yt_result.into_playlist() {
-            return Self::from_pl(pl, client, cache_manager, output_template, base_path);
+            return Self::from_pl(
+                pl,
+                yt_dlp_path,
+                client,
+                cache_manager,
+                output_template,
+                base_path,
+            );
         }
 
-        return Err(YtSongError::UnknownError);
+        Err(YtSongError::UnknownError)
     }
     async fn find_cached_song_extension(&self, base_path: &PathBuf) -> Option<String> {
         let id = self.yt_id.clone();
@@ -106,6 +124,7 @@ where
 
     fn from_sv(
         sv: SingleVideo,
+        yt_dlp_path: &'static str,
         client: Client,
         cache_manager: Arc<RwLock<CacheManager<CS>>>,
         output_template: String,
@@ -119,8 +138,9 @@ where
             duration: get_duration(&value),
             extension: get_extension(&value),
             yt_id: value.id,
+            yt_dlp_path,
             client,
-            cache_manager: cache_manager,
+            cache_manager,
             base_path,
             output_template,
         }))
@@ -128,6 +148,7 @@ where
 
     fn from_pl(
         pl: youtube_dl::Playlist,
+        yt_dlp_path: &'static str,
         client: Client,
         cache_manager: Arc<RwLock<CacheManager<CS>>>,
         output_template: String,
@@ -139,6 +160,7 @@ where
             .filter_map(|sv| {
                 Self::from_sv(
                     sv,
+                    yt_dlp_path,
                     client.clone(),
                     cache_manager.clone(),
                     output_template.clone(),
@@ -184,36 +206,45 @@ where
         &self.id
     }
 
+    async fn gain(&self) -> Option<f32> {
+        match self.cache_manager.read().await.get_entry(&self.id) {
+            Some(CachedEntity::Song(song)) => song.gain,
+            _ => None,
+        }
+    }
+
     async fn get_input(&self) -> Input {
-        if let Some(CachedEntity::Song(song)) = self.cache_manager.read().await.get_entry(&self.id)
-        {
-            return song.get_input().await;
+        let cached = match self.cache_manager.read().await.get_entry(&self.id) {
+            Some(CachedEntity::Song(song)) => Some(song.clone()),
+            _ => None,
+        };
+        if let Some(song) = cached {
+            // A broken file is streamed, playing the link from the cache downloads it again
+            if song.is_playable().await {
+                return song.get_input().await;
+            }
         }
         let p = self.id.clone();
         tracing::info!("Getting YT input for {}", p);
-        songbird::input::YoutubeDl::new(self.client.clone(), p).into()
+        songbird::input::YoutubeDl::new_ytdl_like(self.yt_dlp_path, self.client.clone(), p).into()
     }
 }
 
 fn get_title(sv: &SingleVideo) -> String {
-    match sv.title.clone() {
-        Some(title) => return title,
-        None => (),
+    if let Some(title) = sv.title.clone() {
+        return title;
     }
-    match sv.alt_title.clone() {
-        Some(title) => return title,
-        None => (),
+    if let Some(title) = sv.alt_title.clone() {
+        return title;
     }
     "Unknown".to_string()
 }
 fn get_artist(sv: &SingleVideo) -> String {
-    match sv.artist.clone() {
-        Some(artist) => return artist,
-        None => (),
+    if let Some(artist) = sv.artist.clone() {
+        return artist;
     }
-    match sv.uploader.clone() {
-        Some(artist) => return artist,
-        None => (),
+    if let Some(artist) = sv.uploader.clone() {
+        return artist;
     }
     "Unknown".to_string()
 }
@@ -224,13 +255,11 @@ fn get_duration(sv: &SingleVideo) -> Option<u64> {
     }
 }
 fn get_link(value: &SingleVideo) -> Result<String, YtSongError> {
-    match &value.url {
-        Some(url) => return Ok(url.clone()),
-        None => (),
+    if let Some(url) = &value.url {
+        return Ok(url.clone());
     }
-    match &value.webpage_url {
-        Some(url) => return Ok(url.clone()),
-        None => (),
+    if let Some(url) = &value.webpage_url {
+        return Ok(url.clone());
     }
     Err(YtSongError::LinkNotFound)
 }
@@ -254,6 +283,7 @@ where
 
     async fn cache_song(&self) -> Result<CachedSong, Self::E> {
         YoutubeDl::new(&self.id)
+            .youtube_dl_path(self.yt_dlp_path)
             .output_template(&self.output_template)
             .format("ba")
             .download_to_async("./")
@@ -270,8 +300,11 @@ where
             id: self.id.clone(),
             title: self.title.clone(),
             artist: self.artist.clone(),
-            duration: self.duration.clone(),
+            duration: self.duration,
             path: self.base_path.join(format!("{}.{}", self.yt_id, e)),
+            last_played: chrono::Utc::now().timestamp(),
+            gain: None,
+            size: None,
         })
     }
 }
@@ -0,0 +1,197 @@
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use songbird::input::Input;
+
+use crate::{
+    cache_manager::{CacheableSong, CachedSong},
+    common::{Song, SongId},
+};
+
+/// A cached song that has never been played, titled by its id
+pub fn cached_song(id: &str, path: PathBuf) -> CachedSong {
+    CachedSong {
+        id: id.to_string(),
+        path,
+        title: id.to_string(),
+        artist: id.to_string(),
+        duration: None,
+        last_played: 0,
+        gain: None,
+        size: None,
+    }
+}
+
+/// Creates a silent 8kHz mono wav file
+pub fn test_wav(seconds: u32) -> Vec<u8> {
+    wav(8000, 1, &vec![0; 8000 * seconds as usize])
+}
+
+/// Creates a 48kHz mono wav file with a 1kHz sine of the given peak amplitude
+pub fn sine_wav(seconds: u32, amplitude: f64) -> Vec<u8> {
+    wav(48000, 1, &sine(48000 * seconds, amplitude))
+}
+
+/// Creates a 48kHz stereo wav file with a 1kHz sine on the left channel and silence on the right
+pub fn stereo_sine_wav(seconds: u32, amplitude: f64) -> Vec<u8> {
+    let samples = sine(48000 * seconds, amplitude)
+        .into_iter()
+        .flat_map(|s| [s, 0])
+        .collect::<Vec<_>>();
+    wav(48000, 2, &samples)
+}
+
+fn sine(length: u32, amplitude: f64) -> Vec<i16> {
+    (0..length)
+        .map(|i| {
+            let t = i as f64 / 48000.0;
+            (amplitude * (2.0 * std::f64::consts::PI * 1000.0 * t).sin() * i16::MAX as f64) as i16
+        })
+        .collect()
+}
+
+fn wav(sample_rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let block_align = channels * 2;
+    let mut res = vec![];
+    res.extend_from_slice(b"RIFF");
+    res.extend_from_slice(&(36 + data_len).to_le_bytes());
+    res.extend_from_slice(b"WAVEfmt ");
+    res.extend_from_slice(&16u32.to_le_bytes());
+    res.extend_from_slice(&1u16.to_le_bytes());
+    res.extend_from_slice(&channels.to_le_bytes());
+    res.extend_from_slice(&sample_rate.to_le_bytes());
+    res.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    res.extend_from_slice(&block_align.to_le_bytes());
+    res.extend_from_slice(&16u16.to_le_bytes());
+    res.extend_from_slice(b"data");
+    res.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        res.extend_from_slice(&sample.to_le_bytes());
+    }
+    res
+}
+
+/// Serves every connection with the same raw HTTP response
+pub fn serve(response: Vec<u8>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let addr = listener.local_addr().expect("Failed to get address");
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(&response);
+        }
+    });
+    format!("http://{}", addr)
+}
+
+/// A song whose download takes a moment and fails the first `failures` times,
+/// successful downloads are appended to the shared log
+#[derive(Clone)]
+pub struct FakeSong {
+    id: SongId,
+    failures: usize,
+    attempts: Arc<AtomicUsize>,
+    log: Arc<Mutex<Vec<SongId>>>,
+    duration: Option<u64>,
+    error: Option<String>,
+}
+
+impl FakeSong {
+    pub fn new(id: &str, failures: usize, log: Arc<Mutex<Vec<SongId>>>) -> Self {
+        FakeSong {
+            id: id.to_string(),
+            failures,
+            attempts: Arc::new(AtomicUsize::new(0)),
+            log,
+            duration: None,
+            error: None,
+        }
+    }
+
+    pub fn with_duration(mut self, seconds: u64) -> Self {
+        self.duration = Some(seconds);
+        self
+    }
+
+    /// Fails with the error instead of a generic one
+    pub fn with_error(mut self, error: &str) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+
+    pub fn attempts(&self) -> usize {
+        self.attempts.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl Song for FakeSong {
+    fn title(&self) -> &String {
+        &self.id
+    }
+
+    fn artist(&self) -> &String {
+        &self.id
+    }
+
+    fn duration(&self) -> Option<u64> {
+        self.duration
+    }
+
+    fn clone_song(&self) -> Box<dyn Song> {
+        Box::new(self.clone())
+    }
+
+    fn get_id(&self) -> &SongId {
+        &self.id
+    }
+
+    async fn get_input(&self) -> Input {
+        songbird::input::File::new(self.get_path()).into()
+    }
+}
+
+#[async_trait]
+impl CacheableSong for FakeSong {
+    type E = String;
+    fn get_path(&self) -> PathBuf {
+        PathBuf::from(&self.id)
+    }
+
+    async fn cache_song(&self) -> Result<CachedSong, Self::E> {
+        let attempt = self.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        if attempt <= self.failures {
+            return Err(self
+                .error
+                .clone()
+                .unwrap_or_else(|| format!("Attempt {} failed", attempt)));
+        }
+        self.log
+            .lock()
+            .expect("Log lock was poisoned")
+            .push(self.id.clone());
+        Ok(CachedSong {
+            id: self.id.clone(),
+            path: self.get_path(),
+            title: self.id.clone(),
+            artist: self.id.clone(),
+            duration: None,
+            last_played: 0,
+            gain: None,
+            size: None,
+        })
+    }
+}
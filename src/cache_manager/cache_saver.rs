@@ -5,12 +5,12 @@ use std::{
     path::PathBuf,
 };
 
-
 use crate::common::SongId;
 
 use super::CachedEntity;
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum CacheSaverError {
     FailedToCreateFile,
     FailedToParseData,
@@ -40,7 +40,7 @@ impl FileCacheSaver {
 
 impl CacheSaver for FileCacheSaver {
     fn save_cache(&mut self, cache: &HashMap<SongId, CachedEntity>) -> Result<(), CacheSaverError> {
-        let mut file = match File::create(&self.cache_dir.join("cache.json")) {
+        let mut file = match File::create(self.cache_dir.join("cache.json")) {
             Ok(file) => file,
             Err(_) => return Err(CacheSaverError::FailedToCreateFile),
         };
@@ -55,7 +55,7 @@ impl CacheSaver for FileCacheSaver {
     }
 
     fn load_cache(&self) -> Result<HashMap<SongId, CachedEntity>, CacheSaverError> {
-        let file_data = fs::read_to_string(&self.cache_dir.join("cache.json"))
+        let file_data = fs::read_to_string(self.cache_dir.join("cache.json"))
             .map_err(|_| CacheSaverError::FailedToReadFromFile)?;
         let data: HashMap<String, CachedEntity> =
             serde_json::from_str(&file_data).map_err(|_| CacheSaverError::FailedToParseData)?;
@@ -84,7 +84,7 @@ impl CacheSaver for MemoryCacheSaver {
         Ok(())
     }
 
-    fn load_cache(&self) -> Result<HashMap<SongId, super::CachedEntity>,CacheSaverError> {
+    fn load_cache(&self) -> Result<HashMap<SongId, super::CachedEntity>, CacheSaverError> {
         Ok(HashMap::new())
     }
 }
@@ -131,6 +131,9 @@ mod tests {
                 id: "test".to_string(),
                 duration: Some(0),
                 path: PathBuf::from("test"),
+                last_played: 0,
+                gain: None,
+                size: None,
             }),
         );
         let mut cache_saver = FileCacheSaver::new(cache_dir.clone());
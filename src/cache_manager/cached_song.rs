@@ -4,7 +4,10 @@ use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use songbird::input::Input;
 
-use crate::common::{Song, SongId};
+use crate::{
+    audio_manager::read_metadata,
+    common::{Song, SongId},
+};
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct CachedSong {
@@ -13,12 +16,35 @@ pub struct CachedSong {
     pub title: String,
     pub artist: String,
     pub duration: Option<u64>,
+    /// Unix timestamp of when the song was cached or last started playing
+    #[serde(default)]
+    pub last_played: i64,
+    /// Volume multiplier that normalizes the loudness, `None` if the song wasn't analyzed
+    #[serde(default)]
+    pub gain: Option<f32>,
+    /// Size of the file in bytes, read once when the song is added to the cache
+    #[serde(default)]
+    pub size: Option<u64>,
+}
+
+impl CachedSong {
+    /// Whether the cached file exists and can be decoded
+    pub async fn is_playable(&self) -> bool {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || read_metadata(&path).is_some())
+            .await
+            .unwrap_or(false)
+    }
 }
 
 #[async_trait]
 pub trait CacheableSong: Song {
     type E;
     fn get_path(&self) -> PathBuf;
+    /// Songs that can't be stored in the cache are streamed every time
+    fn is_cacheable(&self) -> bool {
+        true
+    }
     async fn cache_song(&self) -> Result<CachedSong, Self::E> {
         Ok(CachedSong {
             id: self.get_id().clone(),
@@ -26,6 +52,9 @@ pub trait CacheableSong: Song {
             title: self.title().clone(),
             artist: self.artist().clone(),
             duration: self.duration(),
+            last_played: chrono::Utc::now().timestamp(),
+            gain: None,
+            size: None,
         })
     }
 }
@@ -52,6 +81,10 @@ impl Song for CachedSong {
         &self.id
     }
 
+    async fn gain(&self) -> Option<f32> {
+        self.gain
+    }
+
     async fn get_input(&self) -> Input {
         let p = self.path.clone();
         tracing::info!("Getting cached input for {} with path: {:?}", self.id, p);
@@ -66,3 +99,24 @@ impl CacheableSong for CachedSong {
         self.path.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs};
+
+    use crate::audio_manager::test_utils::{cached_song, test_wav};
+
+    #[tokio::test]
+    async fn test_cached_song_is_playable() {
+        let tempdir = temp_dir().join("test_cached_song_is_playable");
+        fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        fs::write(tempdir.join("song.wav"), test_wav(1)).expect("Failed to write song");
+        fs::write(tempdir.join("corrupt.wav"), "not audio").expect("Failed to write file");
+
+        let song = |name: &str| cached_song(name, tempdir.join(format!("{name}.wav")));
+        assert!(song("song").is_playable().await);
+        assert!(!song("corrupt").is_playable().await);
+        assert!(!song("missing").is_playable().await);
+        fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+}
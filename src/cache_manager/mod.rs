@@ -1,7 +1,12 @@
 pub mod cache_saver;
 mod cached_song;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
 use serde::{Deserialize, Serialize};
 use tracing::{event, Level};
@@ -10,7 +15,7 @@ use crate::common::{Song, SongId};
 
 use self::cache_saver::CacheSaver;
 
-pub use cached_song::{CachedSong, CacheableSong};
+pub use cached_song::{CacheableSong, CachedSong};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum CachedEntity {
@@ -18,12 +23,45 @@ pub enum CachedEntity {
     Playlist(Vec<SongId>),
 }
 
+/// Files modified more recently than this may belong to a download in progress
+const ORPHAN_GRACE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// What was removed by [`CacheManager::check_integrity`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IntegrityReport {
+    pub removed_songs: usize,
+    pub removed_playlists: usize,
+    pub removed_files: usize,
+}
+
+/// Limits of the cache dir, exceeding them evicts the least recently played songs
+#[derive(Clone, Debug, Default)]
+pub struct CacheLimits {
+    pub max_size: Option<u64>,
+    pub max_files: Option<usize>,
+}
+
+impl CacheLimits {
+    fn exceeded(&self, size: u64, files: usize) -> bool {
+        self.max_size.is_some_and(|max| size > max) || self.max_files.is_some_and(|max| files > max)
+    }
+}
+
+/// Tells which songs are queued or playing, their files must not be evicted
+pub trait SongsInUse: Send + Sync {
+    /// `None` if the songs can't be read right now
+    fn songs_in_use(&self) -> Option<Vec<SongId>>;
+}
+
 pub struct CacheManager<CS>
 where
     CS: CacheSaver,
 {
     cache_saver: CS,
     cache: HashMap<SongId, CachedEntity>,
+    cache_dir: PathBuf,
+    limits: CacheLimits,
+    in_use: Vec<Box<dyn SongsInUse>>,
 }
 
 impl<CS> CacheManager<CS>
@@ -34,8 +72,21 @@ where
         CacheManager {
             cache: HashMap::new(),
             cache_saver,
+            cache_dir: PathBuf::new(),
+            limits: CacheLimits::default(),
+            in_use: vec![],
         }
     }
+    /// Keeps the songs that are in use from being evicted
+    pub fn protect(&mut self, songs: impl SongsInUse + 'static) {
+        self.in_use.push(Box::new(songs));
+    }
+    /// Only songs stored inside `cache_dir` are evicted when the limits are exceeded
+    pub fn with_limits(mut self, cache_dir: impl Into<PathBuf>, limits: CacheLimits) -> Self {
+        self.cache_dir = cache_dir.into();
+        self.limits = limits;
+        self
+    }
     pub fn load_cache(&mut self) {
         self.cache = match self.cache_saver.load_cache() {
             Ok(cache) => cache,
@@ -54,8 +105,183 @@ where
     pub fn get_entry(&self, id: &str) -> Option<&CachedEntity> {
         self.cache.get(id)
     }
-    pub fn add_entry(&mut self, id: SongId, entity: CachedEntity) {
+    pub fn add_entry(&mut self, id: SongId, mut entity: CachedEntity) {
+        let is_song = matches!(entity, CachedEntity::Song(_));
+        if let CachedEntity::Song(song) = &mut entity {
+            song.size = song
+                .size
+                .or_else(|| fs::metadata(&song.path).map(|m| m.len()).ok());
+        }
         self.cache.insert(id, entity);
+        if is_song {
+            self.evict();
+        }
+    }
+    /// Marks a song as played now
+    pub fn touch(&mut self, id: &str) {
+        if let Some(CachedEntity::Song(song)) = self.cache.get_mut(id) {
+            song.last_played = chrono::Utc::now().timestamp();
+        }
+    }
+    /// Removes the least recently played songs and their files until the cache is within limits,
+    /// songs that are queued or playing are kept
+    pub fn evict(&mut self) {
+        let mut in_use = HashSet::new();
+        for songs in &self.in_use {
+            match songs.songs_in_use() {
+                Some(ids) => in_use.extend(ids),
+                // Evicting without knowing what is queued could remove a song that is about to play
+                None => return,
+            }
+        }
+        // Songs cached before sizes were stored are read once
+        for entity in self.cache.values_mut() {
+            if let CachedEntity::Song(song) = entity {
+                if song.size.is_none() && song.path.starts_with(&self.cache_dir) {
+                    song.size = fs::metadata(&song.path).map(|m| m.len()).ok();
+                }
+            }
+        }
+        let songs = self
+            .cache
+            .values()
+            .filter_map(|e| match e {
+                CachedEntity::Song(song) if song.path.starts_with(&self.cache_dir) => Some((
+                    song.last_played,
+                    song.size?,
+                    song.id.clone(),
+                    song.path.clone(),
+                )),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut size = songs.iter().map(|(_, s, _, _)| s).sum::<u64>();
+        let mut files = songs.len();
+        let mut evictable = songs
+            .into_iter()
+            .filter(|(_, _, id, _)| !in_use.contains(id))
+            .collect::<Vec<_>>();
+        evictable.sort();
+        for (_, song_size, id, path) in evictable {
+            if !self.limits.exceeded(size, files) {
+                break;
+            }
+            event!(Level::INFO, "Evicting {} from cache", id);
+            if let Err(e) = std::fs::remove_file(&path) {
+                event!(
+                    Level::ERROR,
+                    "Failed to remove cached file {:?}: {}",
+                    path,
+                    e
+                );
+            }
+            self.cache.remove(&id);
+            size -= song_size;
+            files -= 1;
+        }
+    }
+    /// Removes songs whose file is missing or not decodable, playlists with missing songs
+    /// and files in the cache dir that don't belong to any song
+    pub fn check_integrity(&mut self, decodes: impl Fn(&Path) -> bool) -> IntegrityReport {
+        let mut report = IntegrityReport::default();
+        let broken = self
+            .cache
+            .values()
+            .filter_map(|e| match e {
+                CachedEntity::Song(song) if !song.path.is_file() || !decodes(&song.path) => {
+                    Some(song.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for song in broken {
+            event!(Level::WARN, "Removing broken cache entry {}", song.id);
+            if song.path.is_file() && song.path.starts_with(&self.cache_dir) {
+                if let Err(e) = fs::remove_file(&song.path) {
+                    event!(
+                        Level::ERROR,
+                        "Failed to remove cached file {:?}: {}",
+                        song.path,
+                        e
+                    );
+                }
+            }
+            self.cache.remove(&song.id);
+            report.removed_songs += 1;
+        }
+
+        let dangling = self
+            .cache
+            .iter()
+            .filter_map(|(id, e)| match e {
+                CachedEntity::Playlist(ids) if ids.iter().any(|i| !self.cache.contains_key(i)) => {
+                    Some(id.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for id in dangling {
+            event!(Level::WARN, "Removing playlist {} with missing songs", id);
+            self.cache.remove(&id);
+            report.removed_playlists += 1;
+        }
+
+        for path in self.orphaned_files() {
+            event!(Level::INFO, "Removing orphaned file {:?}", path);
+            match fs::remove_file(&path) {
+                Ok(_) => report.removed_files += 1,
+                Err(e) => event!(
+                    Level::ERROR,
+                    "Failed to remove orphaned file {:?}: {}",
+                    path,
+                    e
+                ),
+            }
+        }
+        report
+    }
+    /// Files in the cache dir that no song refers to, json files are left alone
+    /// since the cache and the saved queues are stored next to the songs
+    fn orphaned_files(&self) -> Vec<PathBuf> {
+        if self.cache_dir.as_os_str().is_empty() {
+            return vec![];
+        }
+        let entries = match fs::read_dir(&self.cache_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                event!(
+                    Level::ERROR,
+                    "Failed to read cache dir {:?}: {}",
+                    self.cache_dir,
+                    e
+                );
+                return vec![];
+            }
+        };
+        let known = self
+            .cache
+            .values()
+            .filter_map(|e| match e {
+                CachedEntity::Song(song) => song.path.file_name(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        entries
+            .flatten()
+            .filter(|entry| {
+                let recent = entry
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .and_then(|m| SystemTime::now().duration_since(m).ok())
+                    .is_none_or(|age| age < ORPHAN_GRACE_PERIOD);
+                entry.file_type().is_ok_and(|t| t.is_file())
+                    && !recent
+                    && !known.contains(&entry.file_name().as_os_str())
+            })
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_none_or(|e| e != "json"))
+            .collect()
     }
     pub fn _remove_song(&mut self, id: impl ToString) {
         self.cache.remove(&id.to_string());
@@ -83,3 +309,144 @@ where
         self.cache.contains_key(id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs};
+
+    use super::{cache_saver::MemoryCacheSaver, *};
+    use crate::audio_manager::test_utils;
+
+    impl SongsInUse for Vec<SongId> {
+        fn songs_in_use(&self) -> Option<Vec<SongId>> {
+            Some(self.clone())
+        }
+    }
+
+    impl SongsInUse for Option<Vec<SongId>> {
+        fn songs_in_use(&self) -> Option<Vec<SongId>> {
+            self.clone()
+        }
+    }
+
+    fn cached_song(id: &str, path: PathBuf, last_played: i64) -> CachedEntity {
+        fs::write(&path, [0; 10]).expect("Failed to write file");
+        CachedEntity::Song(CachedSong {
+            last_played,
+            ..test_utils::cached_song(id, path)
+        })
+    }
+
+    #[test]
+    fn test_cache_manager_evicts_least_recently_played() {
+        let tempdir = temp_dir().join("test_cache_manager_evicts_least_recently_played");
+        let outside = temp_dir().join("test_cache_manager_evicts_outside.mp3");
+        fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        let limits = CacheLimits {
+            max_size: Some(25),
+            max_files: None,
+        };
+        let mut cache_manager =
+            CacheManager::new(MemoryCacheSaver::new()).with_limits(&tempdir, limits);
+
+        cache_manager.add_entry(
+            "outside".to_string(),
+            cached_song("outside", outside.clone(), 0),
+        );
+        cache_manager.add_entry(
+            "old".to_string(),
+            cached_song("old", tempdir.join("old.mp3"), 1),
+        );
+        cache_manager.add_entry(
+            "new".to_string(),
+            cached_song("new", tempdir.join("new.mp3"), 3),
+        );
+        cache_manager.touch("old");
+        cache_manager.add_entry(
+            "recent".to_string(),
+            cached_song("recent", tempdir.join("recent.mp3"), 4),
+        );
+
+        assert!(cache_manager.get_entry("outside").is_some());
+        assert!(outside.exists());
+        assert!(cache_manager.get_entry("old").is_some());
+        assert!(cache_manager.get_entry("new").is_none());
+        assert!(!tempdir.join("new.mp3").exists());
+        assert!(cache_manager.get_entry("recent").is_some());
+
+        cache_manager.limits.max_files = Some(1);
+        cache_manager.protect(vec!["recent".to_string()]);
+        cache_manager.evict();
+        assert!(cache_manager.get_entry("recent").is_some());
+        assert!(cache_manager.get_entry("old").is_none());
+
+        // Nothing is evicted while the songs in use can't be read
+        cache_manager.protect(None);
+        cache_manager.limits.max_files = Some(0);
+        cache_manager.evict();
+        assert!(cache_manager.get_entry("recent").is_some());
+
+        fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+        fs::remove_file(&outside).expect("Failed to remove file");
+    }
+
+    #[test]
+    fn test_cache_manager_check_integrity() {
+        let tempdir = temp_dir().join("test_cache_manager_check_integrity");
+        fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        let mut cache_manager = CacheManager::new(MemoryCacheSaver::new())
+            .with_limits(&tempdir, CacheLimits::default());
+        let old = SystemTime::now() - ORPHAN_GRACE_PERIOD * 2;
+        let write_old = |name: &str| {
+            let path = tempdir.join(name);
+            fs::File::create(&path)
+                .and_then(|f| f.set_modified(old))
+                .expect("Failed to write file");
+            path
+        };
+
+        cache_manager.add_entry(
+            "ok".to_string(),
+            cached_song("ok", tempdir.join("ok.mp3"), 0),
+        );
+        cache_manager.add_entry(
+            "bad".to_string(),
+            cached_song("bad", tempdir.join("bad.txt"), 0),
+        );
+        cache_manager.add_entry(
+            "gone".to_string(),
+            cached_song("gone", tempdir.join("gone.mp3"), 0),
+        );
+        fs::remove_file(tempdir.join("gone.mp3")).expect("Failed to remove file");
+        cache_manager.add_entry(
+            "playlist".to_string(),
+            CachedEntity::Playlist(vec!["ok".to_string(), "gone".to_string()]),
+        );
+        cache_manager.add_entry(
+            "full".to_string(),
+            CachedEntity::Playlist(vec!["ok".to_string()]),
+        );
+        let orphan = write_old("orphan.mp3");
+        let json = write_old("cache.json");
+        let recent = tempdir.join("downloading.mp3");
+        fs::write(&recent, []).expect("Failed to write file");
+
+        let report = cache_manager.check_integrity(|p| p.extension().is_some_and(|e| e == "mp3"));
+        assert_eq!(
+            report,
+            IntegrityReport {
+                removed_songs: 2,
+                removed_playlists: 1,
+                removed_files: 1,
+            }
+        );
+        assert!(cache_manager.get_entry("ok").is_some());
+        assert!(cache_manager.get_entry("full").is_some());
+        assert!(cache_manager.get_entry("playlist").is_none());
+        assert!(!tempdir.join("bad.txt").exists());
+        assert!(!orphan.exists());
+        assert!(json.exists());
+        assert!(recent.exists());
+        fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+}
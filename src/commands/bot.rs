@@ -1,12 +1,14 @@
 use std::sync::Arc;
 
-use serenity::
-    all::{ChannelId, GuildId}
-;
+use serenity::all::{ChannelId, GuildId};
 use songbird::Songbird;
 use tokio::sync::RwLock;
 
-use crate::common::{CommandError, DiscordQueueManager};
+use crate::{
+    audio_manager::read_metadata,
+    cache_manager::IntegrityReport,
+    common::{CommandError, DiscordCacheManager, DiscordQueueManager},
+};
 
 pub async fn join(
     channel_id: ChannelId,
@@ -24,9 +26,7 @@ pub async fn join(
     }
 }
 
-pub async fn leave(
-    queue_manager: Arc<RwLock<DiscordQueueManager>>,
-) -> Result<(), CommandError> {
+pub async fn leave(queue_manager: Arc<RwLock<DiscordQueueManager>>) -> Result<(), CommandError> {
     let call = queue_manager.write().await.call_left().await;
     match call {
         Some(call) => {
@@ -41,3 +41,11 @@ pub async fn leave(
     }
 }
 
+pub async fn check_cache(cache_manager: Arc<RwLock<DiscordCacheManager>>) -> IntegrityReport {
+    let mut cache_manager = cache_manager.write().await;
+    let report = tokio::task::block_in_place(|| {
+        cache_manager.check_integrity(|p| read_metadata(p).is_some())
+    });
+    cache_manager.save_cache();
+    report
+}
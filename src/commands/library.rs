@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use serenity::all::CreateEmbed;
+use tokio::sync::RwLock;
+
+use crate::{
+    audio_manager::{LocalLibrary, ResolvedLink},
+    common::{CommandError, DiscordAudioManager, DiscordQueueManager, Song},
+    queue_manager::{QueueEntry, QueuePosition, Requester},
+};
+
+use super::queue::{self, Added};
+
+pub fn search(library: Arc<LocalLibrary>, query: &str) -> Vec<CreateEmbed> {
+    let songs = library
+        .search(query)
+        .into_iter()
+        .map(|s| QueueEntry::new(s.clone_song(), None))
+        .collect();
+    queue::list_songs(songs, "Library")
+}
+
+pub async fn add(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    audio_manager: Arc<RwLock<DiscordAudioManager>>,
+    library: Arc<LocalLibrary>,
+    query: &str,
+    requester: u64,
+) -> Result<Box<dyn Song>, CommandError> {
+    let song = library
+        .search(query)
+        .first()
+        .map(|s| s.clone_song())
+        .ok_or(CommandError::NoSearchResults(query.to_string()))?;
+    let ids = vec![song.get_id().clone()];
+    add_songs(queue_manager, audio_manager, ids, requester).await?;
+    Ok(song)
+}
+
+pub async fn album(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    audio_manager: Arc<RwLock<DiscordAudioManager>>,
+    library: Arc<LocalLibrary>,
+    name: &str,
+    requester: u64,
+) -> Result<(String, Added), CommandError> {
+    let songs = library.album(name);
+    let album = songs
+        .first()
+        .and_then(|s| s.album())
+        .cloned()
+        .ok_or(CommandError::NoSearchResults(name.to_string()))?;
+    let ids = songs.iter().map(|s| s.get_id().clone()).collect();
+    let added = add_songs(queue_manager, audio_manager, ids, requester).await?;
+    Ok((album, added))
+}
+
+async fn add_songs(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    audio_manager: Arc<RwLock<DiscordAudioManager>>,
+    ids: Vec<String>,
+    requester: u64,
+) -> Result<Added, CommandError> {
+    let resolved = {
+        let mut audio_manager = audio_manager.write().await;
+        let mut resolved = ResolvedLink::default();
+        for id in ids {
+            let link = audio_manager
+                .resolve_link(&id)
+                .await
+                .map_err(CommandError::LinkHandling)?;
+            resolved.append(link);
+        }
+        resolved
+    };
+    let requester = Requester::new(requester);
+    let songs = resolved
+        .songs
+        .iter()
+        .map(|song| QueueEntry::new(song.clone_song(), Some(requester)))
+        .collect();
+    let added = queue::add_within_limits(&queue_manager, songs, QueuePosition::Back).await?;
+    audio_manager.read().await.cache(resolved, &added.ids);
+    queue::prioritize_downloads(&queue_manager, &audio_manager).await;
+    Ok(added)
+}
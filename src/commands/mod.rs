@@ -1,15 +1,36 @@
+use std::{sync::Arc, time::Duration};
+
 use poise::CreateReply;
+use serenity::all::{
+    Attachment, ComponentInteractionCollector, ComponentInteractionDataKind, CreateActionRow,
+    CreateInteractionResponse, CreateSelectMenu, CreateSelectMenuKind, CreateSelectMenuOption,
+    Mentionable, Message, Permissions, Role, RoleId, User,
+};
+use tokio::sync::RwLock;
 
 use crate::{
-    common::{CommandError, Context, DataRegistryError, Error},
-    queue_manager::LoopMode,
+    audio_manager::is_link,
+    common::{
+        CommandError, Context, DataRegistryError, DiscordAudioManager, DiscordQueueManager, Error,
+    },
+    queue_manager::{
+        EqBand, FilterKind, LoopMode, QueuePosition, QueueRange, SeekPosition, SkipVote,
+    },
 };
 
-mod bot;
+use self::permissions::RequiredPermission;
+
+pub mod bot;
+mod library;
+mod permissions;
 mod player;
 mod queue;
 mod utils;
 
+const SEARCH_RESULT_COUNT: usize = 5;
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(60);
+const SELECT_MENU_TEXT_LENGTH: usize = 100;
+
 /// Ping the bot!
 #[poise::command(slash_command, prefix_command)]
 pub async fn ping(ctx: Context<'_>) -> Result<(), Error> {
@@ -85,14 +106,177 @@ pub async fn resume(ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
+/// Seek within the current song
+/// accepts an absolute position (1:23) or a relative offset (+30, -15)
+#[poise::command(slash_command, prefix_command)]
+pub async fn seek(
+    ctx: Context<'_>,
+    #[description = "Position to seek to"] position: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let position = position
+        .parse::<SeekPosition>()
+        .map_err(|_e| CommandError::InvalidSeekPosition(position))?;
+    let position = player::seek(queue_manager, position).await?;
+    let position = chrono::Duration::from_std(position).unwrap_or_default();
+    let reply = CreateReply::default()
+        .content(format!("Seeked to {}", queue::format_duration(&position)))
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Set the playback volume in percent (0-200)
+/// if no volume is provided, show the current volume
+#[poise::command(slash_command, prefix_command)]
+pub async fn volume(
+    ctx: Context<'_>,
+    #[description = "Volume in percent"]
+    #[min = 0]
+    #[max = 200]
+    volume: Option<u16>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let volume = player::volume(queue_manager, volume.map(|v| v.min(200))).await?;
+    let reply = CreateReply::default()
+        .content(format!("Volume is set to {volume}%"))
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Set the crossfade between songs in seconds (0-15)
+/// if no duration is provided, show it
+#[poise::command(slash_command, prefix_command)]
+pub async fn crossfade(
+    ctx: Context<'_>,
+    #[description = "Crossfade in seconds"]
+    #[min = 0]
+    #[max = 15]
+    seconds: Option<u64>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let seconds = player::crossfade(queue_manager, seconds.map(|s| s.min(15))).await?;
+    let content = match seconds {
+        0 => "Crossfade is disabled".to_string(),
+        seconds => format!("Crossfade is set to {seconds} seconds"),
+    };
+    let reply = CreateReply::default()
+        .content(content)
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Apply an audio filter, presets and mono toggle
+/// if no filter is provided, show the active filters
+#[poise::command(slash_command, prefix_command)]
+pub async fn filter(
+    ctx: Context<'_>,
+    #[description = "Filter to apply"] kind: Option<FilterKind>,
+    #[description = "Speed (0.5-2) or gain in dB (bass 0-20, EQ -12 to 12)"] value: Option<f32>,
+    #[description = "EQ band"] band: Option<EqBand>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let filters = player::filter(queue_manager, kind, value, band).await?;
+    let reply = CreateReply::default()
+        .content(format!("Active filters: {}", filters))
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
 /// Skip the current song
+/// with vote skip enabled this votes, unless you are a DJ or requested the song
 #[poise::command(slash_command, prefix_command)]
 pub async fn skip(ctx: Context<'_>) -> Result<(), Error> {
     let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
     let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
-    let skipped = player::skip(queue_manager).await?;
+    let (roles, _) = utils::get_author_roles(ctx).await;
+    let listeners = || utils::get_listeners(ctx);
+    let vote = player::skip(queue_manager, ctx.author().id.get(), roles, listeners).await?;
+    let reply = match vote {
+        SkipVote::Skipped(skipped) => CreateReply::default()
+            .content(format!("Skipped {}", skipped.title()))
+            .ephemeral(true),
+        SkipVote::Voted { votes, required } => {
+            CreateReply::default().content(format!("Voted to skip ({votes}/{required})"))
+        }
+    };
+    ctx.send(reply.reply(true)).await?;
+    Ok(())
+}
+
+/// Set the percent of listeners needed to skip a song
+/// 0 disables voting, if none is provided, show it
+#[poise::command(slash_command, prefix_command)]
+pub async fn voteskip(
+    ctx: Context<'_>,
+    #[description = "Percentage of listeners"]
+    #[min = 0]
+    #[max = 100]
+    percentage: Option<u8>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let percentage = player::vote_skip(queue_manager, percentage.map(|p| p.min(100))).await?;
+    let content = match percentage {
+        0 => "Vote skip is disabled".to_string(),
+        percentage => format!("Skipping needs votes from {percentage}% of the listeners"),
+    };
+    let reply = CreateReply::default()
+        .content(content)
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Set the role that can skip without voting
+/// if no role is provided, show it
+#[poise::command(slash_command, prefix_command)]
+pub async fn djrole(
+    ctx: Context<'_>,
+    #[description = "DJ role"] role: Option<Role>,
+    #[description = "Remove the DJ role"] remove: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let role = player::dj_role(
+        queue_manager,
+        role.map(|r| r.id.get()),
+        remove.unwrap_or(false),
+    )
+    .await?;
+    let content = match role {
+        Some(role) => format!("The DJ role is {}", RoleId::new(role).mention()),
+        None => "No DJ role is set".to_string(),
+    };
+    let reply = CreateReply::default()
+        .content(content)
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Play the previous song
+/// the current song will be played next
+#[poise::command(slash_command, prefix_command, aliases("back"))]
+pub async fn previous(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let song = player::previous(queue_manager).await?;
     let reply = CreateReply::default()
-        .content(format!("Skipped {}", skipped.title()))
+        .content(format!("Playing previous song {}", song.title()))
         .reply(true)
         .ephemeral(true);
     ctx.send(reply).await?;
@@ -131,6 +315,63 @@ pub async fn shuffle(ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
+/// Let requesters take turns instead of playing songs in order
+/// if no value is provided, show the mode
+#[poise::command(slash_command, prefix_command)]
+pub async fn fair(
+    ctx: Context<'_>,
+    #[description = "Enable fair queueing"] enabled: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let content = match queue::fair(queue_manager, enabled).await? {
+        true => "Fair queueing is enabled, requesters take turns",
+        false => "Fair queueing is disabled, songs play in the order they were added",
+    };
+    let reply = CreateReply::default()
+        .content(content)
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Limit what can be added to the queue, 0 removes a limit
+/// if no limit is provided, show them
+#[poise::command(slash_command, prefix_command)]
+pub async fn limits(
+    ctx: Context<'_>,
+    #[description = "Songs each member can have in the queue"] per_member: Option<usize>,
+    #[description = "Songs in the whole queue"] queue_length: Option<usize>,
+    #[description = "Length of a single song in minutes"] minutes: Option<u64>,
+    #[description = "Songs a single link can add"] per_link: Option<usize>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let seconds = minutes.map(|m| m.saturating_mul(60));
+    let limits = queue::limits(queue_manager, per_member, queue_length, seconds, per_link).await?;
+    let limit = |n: usize| match n {
+        0 => "no limit".to_string(),
+        n => n.to_string(),
+    };
+    let content = format!(
+        "Songs per member: {}\nQueue length: {}\nSong length: {}\nSongs per link: {}",
+        limit(limits.user_songs),
+        limit(limits.queue_length),
+        match limits.duration {
+            0 => "no limit".to_string(),
+            s => format!("{}:{:02}", s / 60, s % 60),
+        },
+        limit(limits.playlist_size),
+    );
+    let reply = CreateReply::default()
+        .content(content)
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
 /// Show the current song
 /// if saved_queue_name is provided, show the contents of the saved queue
 #[poise::command(slash_command, prefix_command)]
@@ -156,18 +397,8 @@ pub async fn list_saved(ctx: Context<'_>, name: String) -> Result<(), Error> {
             .get_saved_queue(name)
             .ok_or(CommandError::EmptyQueue)?
     };
-    let songs = {
-        let mut audio_manager = audio_manager.write().await;
-        let mut res = vec![];
-        for s in saved_queue {
-            let song = audio_manager.handle_link(&s).await;
-            if let Ok(mut song) = song {
-                res.append(&mut song);
-            }
-        }
-        res
-    };
-    let embeds = queue::list_songs(songs);
+    let (songs, _) = queue::resolve_saved_entries(&audio_manager, saved_queue, None).await;
+    let embeds = queue::list_songs(songs, "Queue");
     if embeds.is_empty() {
         let reply = CreateReply::default()
             .content("The queue is empty")
@@ -202,9 +433,131 @@ pub async fn queue(ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
+/// Show the recently played songs
+#[poise::command(slash_command, prefix_command)]
+pub async fn history(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let embeds = queue::history(queue_manager).await?;
+    if embeds.is_empty() {
+        let reply = CreateReply::default()
+            .content("No songs have been played yet")
+            .reply(true)
+            .ephemeral(true);
+        ctx.send(reply).await?;
+    }
+    for e in embeds {
+        let reply = CreateReply::default().reply(true).ephemeral(true).embed(e);
+        ctx.send(reply).await?;
+    }
+    Ok(())
+}
+
 /// Add a song to the queue
+/// accepts a link, a search query or an audio file
 #[poise::command(slash_command, prefix_command)]
-pub async fn add(ctx: Context<'_>, url: String) -> Result<(), Error> {
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "Audio file to add"] attachment: Option<Attachment>,
+    #[description = "Link or search query"]
+    #[rest]
+    url: Option<String>,
+) -> Result<(), Error> {
+    add_at(ctx, attachment, url, QueuePosition::Back).await
+}
+
+/// Add a song at a position in the queue
+/// accepts a link, a search query or an audio file
+#[poise::command(slash_command, prefix_command)]
+pub async fn insert(
+    ctx: Context<'_>,
+    #[description = "Position in the queue (1-based)"]
+    #[min = 1]
+    position: usize,
+    #[description = "Audio file to add"] attachment: Option<Attachment>,
+    #[description = "Link or search query"]
+    #[rest]
+    url: Option<String>,
+) -> Result<(), Error> {
+    let position = QueuePosition::Index(position.saturating_sub(1));
+    add_at(ctx, attachment, url, position).await
+}
+
+/// Add a song to the front of the queue
+/// accepts a link, a search query or an audio file
+#[poise::command(slash_command, prefix_command)]
+pub async fn playnext(
+    ctx: Context<'_>,
+    #[description = "Audio file to add"] attachment: Option<Attachment>,
+    #[description = "Link or search query"]
+    #[rest]
+    url: Option<String>,
+) -> Result<(), Error> {
+    add_at(ctx, attachment, url, QueuePosition::Next).await
+}
+
+/// Play a song now, the current song plays after it
+/// accepts a link, a search query or an audio file
+#[poise::command(slash_command, prefix_command)]
+pub async fn playnow(
+    ctx: Context<'_>,
+    #[description = "Audio file to add"] attachment: Option<Attachment>,
+    #[description = "Link or search query"]
+    #[rest]
+    url: Option<String>,
+) -> Result<(), Error> {
+    add_at(ctx, attachment, url, QueuePosition::Now).await
+}
+
+async fn add_at(
+    ctx: Context<'_>,
+    attachment: Option<Attachment>,
+    url: Option<String>,
+    position: QueuePosition,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let audio_manager = utils::get_audio_manager(ctx).await?;
+    if let (None, Some(url)) = (&attachment, &url) {
+        if !is_link(url) {
+            let query = url.clone();
+            return add_from_search(ctx, queue_manager, audio_manager, query, position).await;
+        }
+    }
+    let reply = CreateReply::default()
+        .content("Adding song to the queue (it may take a while)")
+        .reply(true)
+        .ephemeral(true);
+    let r = ctx.send(reply).await?;
+    let requester = ctx.author().id.get();
+    let added = match (attachment, url) {
+        (Some(attachment), _) => {
+            let attachments = vec![attachment];
+            queue::add_attachments(
+                queue_manager,
+                audio_manager,
+                attachments,
+                requester,
+                position,
+            )
+            .await?
+        }
+        (None, Some(url)) => {
+            queue::add(queue_manager, audio_manager, url, requester, position).await?
+        }
+        (None, None) => return Err(CommandError::NoSongProvided),
+    };
+    let reply = CreateReply::default()
+        .content(added.to_string())
+        .reply(true)
+        .ephemeral(true);
+    r.edit(ctx, reply).await?;
+    Ok(())
+}
+
+/// Add the audio files attached to a message to the queue
+#[poise::command(context_menu_command = "Add to queue")]
+pub async fn add_message_attachments(ctx: Context<'_>, message: Message) -> Result<(), Error> {
     let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
     let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
     let audio_manager = utils::get_audio_manager(ctx).await?;
@@ -213,23 +566,174 @@ pub async fn add(ctx: Context<'_>, url: String) -> Result<(), Error> {
         .reply(true)
         .ephemeral(true);
     let r = ctx.send(reply).await?;
-    let n = queue::add(queue_manager, audio_manager, url).await?;
+    let requester = ctx.author().id.get();
+    let attachments = message.attachments;
+    let position = QueuePosition::Back;
+    let added = queue::add_attachments(
+        queue_manager,
+        audio_manager,
+        attachments,
+        requester,
+        position,
+    )
+    .await?;
     let reply = CreateReply::default()
-        .content(format!("Added {} song(s) to the queue", n))
+        .content(added.to_string())
         .reply(true)
         .ephemeral(true);
     r.edit(ctx, reply).await?;
     Ok(())
 }
 
-/// Remove a song from the queue
+async fn add_from_search(
+    ctx: Context<'_>,
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    audio_manager: Arc<RwLock<DiscordAudioManager>>,
+    query: String,
+    position: QueuePosition,
+) -> Result<(), Error> {
+    let reply = CreateReply::default()
+        .content(format!("Searching for {query}"))
+        .reply(true)
+        .ephemeral(true);
+    let r = ctx.send(reply).await?;
+    let songs = queue::search(audio_manager.clone(), &query, SEARCH_RESULT_COUNT).await?;
+
+    let custom_id = format!("{}-search", ctx.id());
+    let options = songs
+        .iter()
+        .enumerate()
+        .map(|(i, song)| {
+            let description = match song.duration() {
+                Some(d) => format!(
+                    "{} {}",
+                    song.artist(),
+                    queue::format_duration(&chrono::Duration::seconds(d as i64))
+                ),
+                None => song.artist().clone(),
+            };
+            CreateSelectMenuOption::new(truncate(song.title()), i.to_string())
+                .description(truncate(&description))
+        })
+        .collect();
+    let menu = CreateSelectMenu::new(&custom_id, CreateSelectMenuKind::String { options })
+        .placeholder("Select a song");
+    let reply = CreateReply::default()
+        .content(format!("Search results for {query}"))
+        .components(vec![CreateActionRow::SelectMenu(menu)])
+        .reply(true)
+        .ephemeral(true);
+    r.edit(ctx, reply).await?;
+
+    let interaction = ComponentInteractionCollector::new(ctx.serenity_context())
+        .author_id(ctx.author().id)
+        .channel_id(ctx.channel_id())
+        .timeout(SEARCH_TIMEOUT)
+        .filter(move |i| i.data.custom_id == custom_id)
+        .await;
+    let selected = interaction.as_ref().and_then(|i| match &i.data.kind {
+        ComponentInteractionDataKind::StringSelect { values } => {
+            values.first().and_then(|v| v.parse::<usize>().ok())
+        }
+        _ => None,
+    });
+    let song = match selected.and_then(|i| songs.get(i)) {
+        Some(song) => song,
+        None => {
+            let reply = CreateReply::default()
+                .content("No song selected")
+                .components(vec![])
+                .reply(true)
+                .ephemeral(true);
+            r.edit(ctx, reply).await?;
+            return Ok(());
+        }
+    };
+    if let Some(interaction) = interaction {
+        interaction
+            .create_response(ctx, CreateInteractionResponse::Acknowledge)
+            .await?;
+    }
+
+    let reply = CreateReply::default()
+        .content(format!(
+            "Adding {} to the queue (it may take a while)",
+            song.title()
+        ))
+        .components(vec![])
+        .reply(true)
+        .ephemeral(true);
+    r.edit(ctx, reply).await?;
+    let requester = ctx.author().id.get();
+    let link = song.get_id().clone();
+    queue::add(queue_manager, audio_manager, link, requester, position).await?;
+    let reply = CreateReply::default()
+        .content(format!("Added {} to the queue", song.title()))
+        .reply(true)
+        .ephemeral(true);
+    r.edit(ctx, reply).await?;
+    Ok(())
+}
+
+/// Select menu labels and descriptions are limited to 100 characters
+fn truncate(s: &str) -> String {
+    if s.chars().count() <= SELECT_MENU_TEXT_LENGTH {
+        return s.to_string();
+    }
+    let mut res = s
+        .chars()
+        .take(SELECT_MENU_TEXT_LENGTH - 1)
+        .collect::<String>();
+    res.push('…');
+    res
+}
+
+/// Remove songs by position (3) or range (3-10)
+/// or the songs of a member or the duplicates
+#[poise::command(slash_command, prefix_command)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "Position or range, like 3 or 3-10"] songs: Option<String>,
+    #[description = "Remove the songs requested by this member"] user: Option<User>,
+    #[description = "Remove songs that are already in the queue"] duplicates: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let mut removed = vec![];
+    if let Some(songs) = songs {
+        let range = songs
+            .parse::<QueueRange>()
+            .map_err(|_e| CommandError::InvalidRange(songs))?;
+        removed.extend(queue::remove(queue_manager.clone(), range).await?);
+    }
+    if let Some(user) = user {
+        removed.extend(queue::remove_requested_by(queue_manager.clone(), user.id.get()).await?);
+    }
+    if duplicates == Some(true) {
+        removed.extend(queue::remove_duplicates(queue_manager).await?);
+    }
+    let content = match removed.as_slice() {
+        [] => "No songs were removed".to_string(),
+        [entry] => format!("Removed {}", entry.song.title()),
+        entries => format!("Removed {} songs", entries.len()),
+    };
+    let reply = CreateReply::default()
+        .content(content)
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Skip to a song in the queue (1-based)
+/// the songs before it are removed
 #[poise::command(slash_command, prefix_command)]
-pub async fn remove(ctx: Context<'_>, index: usize) -> Result<(), Error> {
+pub async fn jump(ctx: Context<'_>, #[min = 1] position: usize) -> Result<(), Error> {
     let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
     let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
-    let song = queue::remove(queue_manager, index).await?;
+    let song = queue::jump(queue_manager, position).await?;
     let reply = CreateReply::default()
-        .content(format!("Removed song: {index}. {}", song.title()))
+        .content(format!("Jumped to {position}. {}", song.title()))
         .reply(true)
         .ephemeral(true);
     ctx.send(reply).await?;
@@ -304,13 +808,26 @@ pub async fn saved(ctx: Context<'_>) -> Result<(), Error> {
 
 /// Load a saved queue
 #[poise::command(slash_command, prefix_command)]
-pub async fn load(ctx: Context<'_>, name: String) -> Result<(), Error> {
+pub async fn load(
+    ctx: Context<'_>,
+    name: String,
+    #[description = "Play the saved queue next instead of after the queue"] next: Option<bool>,
+) -> Result<(), Error> {
     let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
     let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
     let audio_manager = utils::get_audio_manager(ctx).await?;
-    let n = queue::load(queue_manager, audio_manager, &name).await?;
+    let position = match next {
+        Some(true) => QueuePosition::Next,
+        _ => QueuePosition::Back,
+    };
+    let requester = ctx.author().id.get();
+    let added = queue::load(queue_manager, audio_manager, &name, requester, position).await?;
     let reply = CreateReply::default()
-        .content(format!("Loaded {n} song(s) from {name}"))
+        .content(format!(
+            "Loaded {} song(s) from {name}{}",
+            added.ids.len(),
+            added.dropped_note()
+        ))
         .reply(true)
         .ephemeral(true);
     ctx.send(reply).await?;
@@ -331,6 +848,240 @@ pub async fn remove_saved(ctx: Context<'_>, name: String) -> Result<(), Error> {
     Ok(())
 }
 
+/// Search and queue songs from the local music library
+#[poise::command(
+    slash_command,
+    prefix_command,
+    subcommands("library_search", "library_add", "library_album"),
+    subcommand_required
+)]
+pub async fn library(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Search the local music library by title, artist or album
+#[poise::command(slash_command, prefix_command, rename = "search")]
+pub async fn library_search(ctx: Context<'_>, #[rest] query: String) -> Result<(), Error> {
+    let library = utils::get_library(ctx).await?;
+    let embeds = library::search(library, &query);
+    if embeds.is_empty() {
+        let reply = CreateReply::default()
+            .content(format!("No songs found for {query}"))
+            .reply(true)
+            .ephemeral(true);
+        ctx.send(reply).await?;
+    }
+    for e in embeds {
+        let reply = CreateReply::default().reply(true).ephemeral(true).embed(e);
+        ctx.send(reply).await?;
+    }
+    Ok(())
+}
+
+/// Add the best matching song from the local music library to the queue
+#[poise::command(slash_command, prefix_command, rename = "add")]
+pub async fn library_add(ctx: Context<'_>, #[rest] query: String) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let audio_manager = utils::get_audio_manager(ctx).await?;
+    let library = utils::get_library(ctx).await?;
+    let requester = ctx.author().id.get();
+    let song = library::add(queue_manager, audio_manager, library, &query, requester).await?;
+    let reply = CreateReply::default()
+        .content(format!("Added {} to the queue", song.title()))
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Add an album from the local music library to the queue
+#[poise::command(slash_command, prefix_command, rename = "album")]
+pub async fn library_album(ctx: Context<'_>, #[rest] name: String) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let audio_manager = utils::get_audio_manager(ctx).await?;
+    let library = utils::get_library(ctx).await?;
+    let requester = ctx.author().id.get();
+    let (album, added) =
+        library::album(queue_manager, audio_manager, library, &name, requester).await?;
+    let reply = CreateReply::default()
+        .content(format!(
+            "Added {} song(s) from {album} to the queue{}",
+            added.ids.len(),
+            added.dropped_note()
+        ))
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Checks the permissions the guild set for the command, runs before every command
+pub async fn command_check(ctx: Context<'_>) -> Result<bool, Error> {
+    let guild_id = match ctx.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return Ok(true),
+    };
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let command = &ctx.command().qualified_name;
+    let (roles, permissions) = utils::get_author_roles(ctx).await;
+    if permissions::is_allowed(queue_manager, command, &roles, permissions).await {
+        return Ok(true);
+    }
+    let reply = CreateReply::default()
+        .content(format!("You aren't allowed to use {command}"))
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(false)
+}
+
+/// Configure who can use each command
+#[poise::command(
+    slash_command,
+    prefix_command,
+    subcommands(
+        "permission_allow",
+        "permission_require",
+        "permission_everyone",
+        "permission_list"
+    ),
+    subcommand_required
+)]
+pub async fn permission(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Allow members with a role to use a command
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "allow",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn permission_allow(
+    ctx: Context<'_>,
+    #[description = "Command name"] command: String,
+    #[description = "Role that can use the command"] role: Role,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    if !utils::command_exists(ctx, &command) {
+        return Err(CommandError::UnknownCommand(command));
+    }
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    permissions::allow_role(queue_manager, command.clone(), role.id.get()).await?;
+    let reply = CreateReply::default()
+        .content(format!("{} can now use {command}", role.mention()))
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Allow members with a permission to use a command
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "require",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn permission_require(
+    ctx: Context<'_>,
+    #[description = "Command name"] command: String,
+    #[description = "Permission that allows using the command"] permission: RequiredPermission,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    if !utils::command_exists(ctx, &command) {
+        return Err(CommandError::UnknownCommand(command));
+    }
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let permissions = Permissions::from(permission);
+    permissions::require_permission(queue_manager, command.clone(), permissions).await?;
+    let reply = CreateReply::default()
+        .content(format!("Members with {permissions} can now use {command}"))
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Allow everyone to use a command
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "everyone",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn permission_everyone(
+    ctx: Context<'_>,
+    #[description = "Command name"] command: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    if !utils::command_exists(ctx, &command) {
+        return Err(CommandError::UnknownCommand(command));
+    }
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    permissions::allow_everyone(queue_manager, command.clone()).await?;
+    let reply = CreateReply::default()
+        .content(format!("Everyone can now use {command}"))
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// List the commands that not everyone can use
+#[poise::command(slash_command, prefix_command, rename = "list")]
+pub async fn permission_list(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let queue_manager = utils::get_queue_manager(ctx, &guild_id).await?;
+    let lines = permissions::list(queue_manager)
+        .await
+        .into_iter()
+        .map(|(command, permission)| {
+            let mut allowed = permission
+                .roles
+                .iter()
+                .map(|role| RoleId::new(*role).mention().to_string())
+                .collect::<Vec<_>>();
+            if !permission.permissions.is_empty() {
+                allowed.push(permission.permissions.to_string());
+            }
+            match allowed.is_empty() {
+                true => format!("{command}: DJ and administrators only"),
+                false => format!("{command}: {}", allowed.join(", ")),
+            }
+        })
+        .collect::<Vec<_>>();
+    let content = match lines.is_empty() {
+        true => "Everyone can use every command".to_string(),
+        false => lines.join("\n"),
+    };
+    let reply = CreateReply::default()
+        .content(content)
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Remove broken cache entries and files that don't belong to any song
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn check_cache(ctx: Context<'_>) -> Result<(), Error> {
+    let cache_manager = utils::get_cache_manager(ctx).await?;
+    let report = bot::check_cache(cache_manager).await;
+    let reply = CreateReply::default()
+        .content(format!(
+            "Removed {} song(s), {} playlist(s) and {} orphaned file(s) from the cache",
+            report.removed_songs, report.removed_playlists, report.removed_files
+        ))
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
 // TODO: make it prettier
 /// Help command
 #[poise::command(slash_command, prefix_command)]
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use poise::ChoiceParameter;
+use serenity::all::Permissions;
+use tokio::sync::RwLock;
+
+use crate::{
+    common::{CommandError, DiscordQueueManager},
+    queue_manager::CommandPermission,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, ChoiceParameter)]
+pub enum RequiredPermission {
+    #[name = "Manage channels"]
+    ManageChannels,
+    #[name = "Manage server"]
+    ManageGuild,
+    #[name = "Manage messages"]
+    ManageMessages,
+    #[name = "Move members"]
+    MoveMembers,
+    #[name = "Mute members"]
+    MuteMembers,
+    Administrator,
+}
+
+impl From<RequiredPermission> for Permissions {
+    fn from(permission: RequiredPermission) -> Self {
+        match permission {
+            RequiredPermission::ManageChannels => Permissions::MANAGE_CHANNELS,
+            RequiredPermission::ManageGuild => Permissions::MANAGE_GUILD,
+            RequiredPermission::ManageMessages => Permissions::MANAGE_MESSAGES,
+            RequiredPermission::MoveMembers => Permissions::MOVE_MEMBERS,
+            RequiredPermission::MuteMembers => Permissions::MUTE_MEMBERS,
+            RequiredPermission::Administrator => Permissions::ADMINISTRATOR,
+        }
+    }
+}
+
+pub async fn is_allowed(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    command: &str,
+    roles: &[u64],
+    permissions: Permissions,
+) -> bool {
+    let queue_manager = queue_manager.read().await;
+    match queue_manager.get_command_permission(command) {
+        Some(permission) => {
+            permission.allows(command, roles, permissions, queue_manager.get_dj_role())
+        }
+        None => true,
+    }
+}
+
+pub async fn allow_role(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    command: String,
+    role: u64,
+) -> Result<(), CommandError> {
+    let mut queue_manager = queue_manager.write().await;
+    let mut permission = queue_manager
+        .get_command_permission(&command)
+        .cloned()
+        .unwrap_or_default();
+    if !permission.roles.contains(&role) {
+        permission.roles.push(role);
+    }
+    queue_manager.set_command_permission(command, Some(permission));
+    Ok(())
+}
+
+pub async fn require_permission(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    command: String,
+    permissions: Permissions,
+) -> Result<(), CommandError> {
+    let mut queue_manager = queue_manager.write().await;
+    let mut permission = queue_manager
+        .get_command_permission(&command)
+        .cloned()
+        .unwrap_or_default();
+    permission.permissions = permissions;
+    queue_manager.set_command_permission(command, Some(permission));
+    Ok(())
+}
+
+pub async fn allow_everyone(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    command: String,
+) -> Result<(), CommandError> {
+    let mut queue_manager = queue_manager.write().await;
+    queue_manager.set_command_permission(command, None);
+    Ok(())
+}
+
+pub async fn list(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+) -> Vec<(String, CommandPermission)> {
+    let queue_manager = queue_manager.read().await;
+    let mut permissions = queue_manager
+        .get_command_permissions()
+        .iter()
+        .map(|(command, permission)| (command.clone(), permission.clone()))
+        .collect::<Vec<_>>();
+    permissions.sort_by(|a, b| a.0.cmp(&b.0));
+    permissions
+}
@@ -1,9 +1,11 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
 use tokio::sync::RwLock;
 
-use crate::{common::{CommandError, DiscordQueueManager, Song}, queue_manager::LoopMode};
-
+use crate::{
+    common::{CommandError, DiscordQueueManager, Song},
+    queue_manager::{EqBand, FilterKind, Filters, LoopMode, SeekPosition, SkipVote},
+};
 
 pub async fn pause(queue_manager: Arc<RwLock<DiscordQueueManager>>) -> Result<(), CommandError> {
     let queue_manager = queue_manager.write().await;
@@ -13,7 +15,6 @@ pub async fn pause(queue_manager: Arc<RwLock<DiscordQueueManager>>) -> Result<()
     }
 }
 
-
 pub async fn resume(queue_manager: Arc<RwLock<DiscordQueueManager>>) -> Result<(), CommandError> {
     let queue_manager = queue_manager.write().await;
     match queue_manager.resume().await {
@@ -22,10 +23,133 @@ pub async fn resume(queue_manager: Arc<RwLock<DiscordQueueManager>>) -> Result<(
     }
 }
 
-pub async fn skip(queue_manager: Arc<RwLock<DiscordQueueManager>>) -> Result<Box<dyn Song>, CommandError> {
+pub async fn seek(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    position: SeekPosition,
+) -> Result<Duration, CommandError> {
+    let queue_manager = queue_manager.write().await;
+    if queue_manager.get_current_song().await.is_none() {
+        return Err(CommandError::NoSongPlaying);
+    }
+    queue_manager
+        .seek(position)
+        .await
+        .map_err(|e| CommandError::SongbirdError(e.into()))
+}
+
+pub async fn volume(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    volume: Option<u16>,
+) -> Result<u16, CommandError> {
+    let mut queue_manager = queue_manager.write().await;
+    if let Some(volume) = volume {
+        queue_manager
+            .set_volume(volume as f32 / 100.0)
+            .await
+            .map_err(|e| CommandError::SongbirdError(e.into()))?;
+    }
+    Ok((queue_manager.get_volume() * 100.0).round() as u16)
+}
+
+pub async fn crossfade(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    seconds: Option<u64>,
+) -> Result<u64, CommandError> {
+    let mut queue_manager = queue_manager.write().await;
+    if let Some(seconds) = seconds {
+        queue_manager.set_crossfade(seconds);
+    }
+    Ok(queue_manager.get_crossfade())
+}
+
+pub async fn filter(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    kind: Option<FilterKind>,
+    value: Option<f32>,
+    band: Option<EqBand>,
+) -> Result<Filters, CommandError> {
+    let mut queue_manager = queue_manager.write().await;
+    if let Some(kind) = kind {
+        let mut filters = queue_manager.get_filters().clone();
+        filters
+            .apply(kind, value, band)
+            .map_err(CommandError::InvalidFilter)?;
+        queue_manager
+            .set_filters(filters)
+            .await
+            .map_err(|e| CommandError::SongbirdError(e.into()))?;
+    }
+    Ok(queue_manager.get_filters().clone())
+}
+
+/// Skips right away for members with the DJ role, the requester of the song
+/// or when vote skip is disabled, otherwise adds the vote of the user
+pub async fn skip(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    user: u64,
+    roles: Vec<u64>,
+    listeners: impl FnOnce() -> Option<HashSet<u64>>,
+) -> Result<SkipVote, CommandError> {
+    let mut queue_manager = queue_manager.write().await;
+    let current_song = match queue_manager.get_current_song().await {
+        Some(current_song) => current_song,
+        None => return Err(CommandError::NoSongPlaying),
+    };
+    let is_dj = queue_manager
+        .get_dj_role()
+        .is_some_and(|role| roles.contains(&role));
+    let is_requester = current_song.entry().requested_by(user);
+    if is_dj || is_requester || queue_manager.get_vote_skip() == 0 {
+        let cs = queue_manager.skip().await;
+        return cs
+            .map(SkipVote::Skipped)
+            .map_err(|e| CommandError::SongbirdError(e.into()));
+    }
+    // Listeners are only needed to count votes
+    let listeners = listeners().ok_or(CommandError::NotInVoiceChannel)?;
+    if !listeners.contains(&user) {
+        return Err(CommandError::NotInVoiceChannel);
+    }
+    queue_manager
+        .vote_skip(user, &listeners)
+        .await
+        .map_err(|e| CommandError::SongbirdError(e.into()))
+}
+
+pub async fn vote_skip(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    percentage: Option<u8>,
+) -> Result<u8, CommandError> {
+    let mut queue_manager = queue_manager.write().await;
+    if let Some(percentage) = percentage {
+        queue_manager.set_vote_skip(percentage);
+    }
+    Ok(queue_manager.get_vote_skip())
+}
+
+pub async fn dj_role(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    role: Option<u64>,
+    remove: bool,
+) -> Result<Option<u64>, CommandError> {
+    let mut queue_manager = queue_manager.write().await;
+    if remove {
+        queue_manager.set_dj_role(None);
+    } else if let Some(role) = role {
+        queue_manager.set_dj_role(Some(role));
+    }
+    Ok(queue_manager.get_dj_role())
+}
+
+pub async fn previous(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+) -> Result<Box<dyn Song>, CommandError> {
     let queue_manager = queue_manager.write().await;
-    let cs = queue_manager.skip().await;
-    cs.map_err(|e| CommandError::SongbirdError(e.into()))
+    queue_manager
+        .previous()
+        .await
+        .map_err(|e| CommandError::SongbirdError(e.into()))?
+        .ok_or(CommandError::EmptyHistory)
 }
 
 pub async fn set_loop(
@@ -35,4 +159,4 @@ pub async fn set_loop(
     let queue_manager = queue_manager.write().await;
     queue_manager.set_loop(loop_mode).await;
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,10 +1,16 @@
-use std::sync::Arc;
+use std::{fmt::Display, sync::Arc};
 
 use chrono::{Duration, Utc};
-use serenity::all::{Color, CreateEmbed};
+use serenity::all::{Attachment, Color, CreateEmbed};
 use tokio::sync::RwLock;
 
-use crate::common::{CommandError, DiscordAudioManager, DiscordQueueManager, Song};
+use crate::{
+    audio_manager::ResolvedLink,
+    common::{CommandError, DiscordAudioManager, DiscordQueueManager, Song, SongId},
+    queue_manager::{
+        QueueEntry, QueueLimit, QueueLimits, QueuePosition, QueueRange, Requester, SavedEntry,
+    },
+};
 
 pub async fn shuffle(queue_manager: Arc<RwLock<DiscordQueueManager>>) -> Result<(), CommandError> {
     let queue_manager = queue_manager.write().await;
@@ -16,6 +22,7 @@ static MAX_EMBED_FIELD_COUNT: usize = 25;
 static PROGRESS_BAR_LENGTH: usize = 20;
 static PROGRESS_BAR_FILL: &str = "▮";
 static PROGRESS_BAR_EMPTY: &str = "▯";
+static LIVE_LABEL: &str = "LIVE";
 
 fn get_progress_bar(
     song_duration: i64,
@@ -27,11 +34,7 @@ fn get_progress_bar(
     let fill_amount = (duration_played as f32 / song_duration as f32 * progress_bar_length as f32)
         .round() as usize;
 
-    let empty_amount = if fill_amount > progress_bar_length {
-        0
-    } else {
-        progress_bar_length - fill_amount
-    };
+    let empty_amount = progress_bar_length.saturating_sub(fill_amount);
 
     format!(
         "[{}{}]",
@@ -61,19 +64,13 @@ pub async fn show(
         Some(song) => song,
         None => return Err(CommandError::NoSongPlaying),
     };
-    let elapsed = Utc::now()
-        .signed_duration_since(current_song.started_at)
-        .num_seconds() as u64;
+    let elapsed = current_song.position().await?.as_secs();
+    if current_song.song.is_live() {
+        return Ok(show_live(current_song.entry(), elapsed).await);
+    }
     let song_duration = current_song.song.duration();
     let timestamp = match song_duration {
-        Some(duration) => {
-            let d = if elapsed > duration as u64 {
-                Duration::seconds(0)
-            } else {
-                Duration::seconds((duration - elapsed) as i64)
-            };
-            Utc::now() + d
-        }
+        Some(duration) => Utc::now() + Duration::seconds(duration.saturating_sub(elapsed) as i64),
         None => Utc::now(),
     };
 
@@ -97,25 +94,68 @@ pub async fn show(
         .field(
             current_song.song.title(),
             format!(
-                "{}\n{} {}/{}",
+                "{}\n{} {}/{}{}",
                 progress_bar,
                 current_song.song.artist(),
                 duration,
-                format_duration(&Duration::seconds(elapsed as i64))
+                format_duration(&Duration::seconds(elapsed as i64)),
+                format_requester(current_song.requester.as_ref())
             ),
             false,
         );
     Ok(embed)
 }
 
-fn map_song((i, song): (usize, &Box<dyn Song>)) -> (String, String, bool) {
+async fn show_live(entry: QueueEntry, elapsed: u64) -> CreateEmbed {
+    let song = entry.song;
+    let (title, station) = match song.now_playing().await {
+        Some(now_playing) => (now_playing, format!("{} ", song.title())),
+        None => (song.title().clone(), "".to_string()),
+    };
+    CreateEmbed::default()
+        .title("Currently Playing")
+        .color(Color::from_rgb(255, 0, 0))
+        .timestamp(Utc::now())
+        .field(
+            title,
+            format!(
+                "{}\n{}{} {}{}",
+                LIVE_LABEL,
+                station,
+                song.artist(),
+                format_duration(&Duration::seconds(elapsed as i64)),
+                format_requester(entry.requester.as_ref())
+            ),
+            false,
+        )
+}
+
+/// Mentions who requested a song on a new line
+fn format_requester(requester: Option<&Requester>) -> String {
+    match requester {
+        Some(requester) => format!(
+            "\nRequested by <@{}> <t:{}:R>",
+            requester.user_id, requester.requested_at
+        ),
+        None => "".to_string(),
+    }
+}
+
+fn map_song((i, entry): (usize, &QueueEntry)) -> (String, String, bool) {
+    let song = &entry.song;
     let d = match song.duration() {
         Some(d) => format_duration(&Duration::seconds(d as i64)),
+        None if song.is_live() => LIVE_LABEL.to_string(),
         None => "".to_string(),
     };
     (
         format!("{}. {}", i + 1, song.title()),
-        format!("{} {}", song.artist(), d),
+        format!(
+            "{} {}{}",
+            song.artist(),
+            d,
+            format_requester(entry.requester.as_ref())
+        ),
         false,
     )
 }
@@ -125,11 +165,54 @@ pub async fn queue(
 ) -> Result<Vec<CreateEmbed>, CommandError> {
     let queue_manager = queue_manager.read().await;
     let queue = queue_manager.get_queue().await;
+    let title = match queue_manager.get_fair_queue() {
+        true => "Queue (fair)",
+        false => "Queue",
+    };
+
+    Ok(list_songs(queue, title))
+}
+
+pub async fn limits(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    user_songs: Option<usize>,
+    queue_length: Option<usize>,
+    duration: Option<u64>,
+    playlist_size: Option<usize>,
+) -> Result<QueueLimits, CommandError> {
+    let mut queue_manager = queue_manager.write().await;
+    let mut limits = queue_manager.get_limits().clone();
+    limits.user_songs = user_songs.unwrap_or(limits.user_songs);
+    limits.queue_length = queue_length.unwrap_or(limits.queue_length);
+    limits.duration = duration.unwrap_or(limits.duration);
+    limits.playlist_size = playlist_size.unwrap_or(limits.playlist_size);
+    if &limits != queue_manager.get_limits() {
+        queue_manager.set_limits(limits.clone());
+    }
+    Ok(limits)
+}
+
+pub async fn fair(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    enabled: Option<bool>,
+) -> Result<bool, CommandError> {
+    let mut queue_manager = queue_manager.write().await;
+    if let Some(enabled) = enabled {
+        queue_manager.set_fair_queue(enabled).await;
+    }
+    Ok(queue_manager.get_fair_queue())
+}
+
+pub async fn history(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+) -> Result<Vec<CreateEmbed>, CommandError> {
+    let queue_manager = queue_manager.read().await;
+    let history = queue_manager.get_history().await;
 
-    Ok(list_songs(queue))
+    Ok(list_songs(history, "History"))
 }
 
-pub fn list_songs(queue: Vec<Box<dyn Song>>) -> Vec<CreateEmbed> {
+pub fn list_songs(queue: Vec<QueueEntry>, name: &str) -> Vec<CreateEmbed> {
     let fields = queue
         .iter()
         .enumerate()
@@ -139,12 +222,12 @@ pub fn list_songs(queue: Vec<Box<dyn Song>>) -> Vec<CreateEmbed> {
     let n = fields.len();
     let title = |i: usize| {
         if n > 1 {
-            format!("Queue ({}/{})", i + 1, n)
+            format!("{} ({}/{})", name, i + 1, n)
         } else {
-            "Queue".to_string()
+            name.to_string()
         }
     };
-    let embeds = fields
+    fields
         .enumerate()
         .map(|(i, chunk)| {
             CreateEmbed::default()
@@ -152,39 +235,213 @@ pub fn list_songs(queue: Vec<Box<dyn Song>>) -> Vec<CreateEmbed> {
                 .color(Color::from_rgb(255, 0, 0))
                 .fields(chunk.to_vec())
         })
-        .collect::<Vec<CreateEmbed>>();
-    embeds
+        .collect::<Vec<CreateEmbed>>()
 }
 
 pub async fn add(
     queue_manager: Arc<RwLock<DiscordQueueManager>>,
     audio_manager: Arc<RwLock<DiscordAudioManager>>,
     link: String,
-) -> Result<usize, CommandError> {
-    let songs = {
+    requester: u64,
+    position: QueuePosition,
+) -> Result<Added, CommandError> {
+    let resolved = {
         let mut audio_manager = audio_manager.write().await;
         audio_manager
-            .handle_link(&link)
+            .resolve_link(&link)
             .await
             .map_err(|_e| CommandError::LinkHandling("Some error".to_string()))?
     };
-    let n = songs.len();
+    let requester = Requester::new(requester);
+    let songs = resolved
+        .songs
+        .iter()
+        .map(|song| QueueEntry::new(song.clone_song(), Some(requester)))
+        .collect();
+    let added = add_within_limits(&queue_manager, songs, position).await?;
+    // Songs dropped by the queue limits are never downloaded
+    audio_manager.read().await.cache(resolved, &added.ids);
+    prioritize_downloads(&queue_manager, &audio_manager).await;
+    Ok(added)
+}
+
+/// How many songs were added and how many the queue limits dropped
+pub struct Added {
+    pub ids: Vec<SongId>,
+    pub dropped: usize,
+    pub limit: Option<QueueLimit>,
+}
+
+impl Added {
+    /// Explains why songs were dropped, empty if all of them were added
+    pub fn dropped_note(&self) -> String {
+        match self.limit {
+            Some(limit) if self.dropped > 0 => format!(
+                ", {} song(s) were dropped: {}",
+                self.dropped,
+                CommandError::from(limit)
+            ),
+            _ => "".to_string(),
+        }
+    }
+}
+
+impl Display for Added {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Added {} song(s) to the queue{}",
+            self.ids.len(),
+            self.dropped_note()
+        )
+    }
+}
+
+/// Adds the songs that fit within the queue limits, fails if none of them fit
+pub async fn add_within_limits(
+    queue_manager: &Arc<RwLock<DiscordQueueManager>>,
+    songs: Vec<QueueEntry>,
+    position: QueuePosition,
+) -> Result<Added, CommandError> {
     let queue_manager = queue_manager.write().await;
-    match queue_manager.add_to_queue(songs).await {
-        Ok(_) => Ok(n),
-        Err(e) => Err(e.into()),
+    let limited = queue_manager.limit(songs).await;
+    if let (true, Some(limit)) = (limited.accepted.is_empty(), limited.limit) {
+        return Err(limit.into());
+    }
+    let ids = limited
+        .accepted
+        .iter()
+        .map(|e| e.song.get_id().clone())
+        .collect();
+    queue_manager
+        .add_to_queue(limited.accepted, position)
+        .await?;
+    Ok(Added {
+        ids,
+        dropped: limited.dropped,
+        limit: limited.limit,
+    })
+}
+
+/// Downloads the songs closest to the head of the queue first
+pub async fn prioritize_downloads(
+    queue_manager: &Arc<RwLock<DiscordQueueManager>>,
+    audio_manager: &Arc<RwLock<DiscordAudioManager>>,
+) {
+    let ids = queue_manager
+        .read()
+        .await
+        .get_queue()
+        .await
+        .iter()
+        .map(|e| e.song.get_id().clone())
+        .collect::<Vec<_>>();
+    audio_manager.read().await.prioritize(&ids);
+}
+
+pub async fn add_attachments(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    audio_manager: Arc<RwLock<DiscordAudioManager>>,
+    attachments: Vec<Attachment>,
+    requester: u64,
+    position: QueuePosition,
+) -> Result<Added, CommandError> {
+    let requester = Requester::new(requester);
+    // Attachments that can't be added aren't downloaded, the duration is checked once they are
+    let mut attachments = attachments;
+    let mut skipped = None;
+    let remaining = {
+        let queue_manager = queue_manager.read().await;
+        queue_manager.remaining_songs(Some(requester)).await
+    };
+    if let Some((remaining, limit)) = remaining {
+        if remaining == 0 && !attachments.is_empty() {
+            return Err(limit.into());
+        }
+        if attachments.len() > remaining {
+            skipped = Some((attachments.len() - remaining, limit));
+            attachments.truncate(remaining);
+        }
+    }
+    let songs = {
+        let mut audio_manager = audio_manager.write().await;
+        let mut res = vec![];
+        let mut error = None;
+        for attachment in attachments {
+            match audio_manager
+                .handle_attachment(
+                    attachment.id.get(),
+                    &attachment.url,
+                    &attachment.filename,
+                    attachment.content_type.as_deref(),
+                    attachment.size.into(),
+                )
+                .await
+            {
+                Ok(song) => res.push(song),
+                Err(e) => error = Some(e),
+            }
+        }
+        match error {
+            Some(e) if res.is_empty() => return Err(CommandError::LinkHandling(e)),
+            _ => res,
+        }
+    };
+    if songs.is_empty() {
+        return Err(CommandError::NoSongProvided);
+    }
+    let songs = songs
+        .into_iter()
+        .map(|song| QueueEntry::new(song, Some(requester)))
+        .collect();
+    let mut added = add_within_limits(&queue_manager, songs, position).await?;
+    if let Some((dropped, limit)) = skipped {
+        added.dropped += dropped;
+        added.limit = Some(limit);
+    }
+    Ok(added)
+}
+
+pub async fn search(
+    audio_manager: Arc<RwLock<DiscordAudioManager>>,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<Box<dyn Song>>, CommandError> {
+    let songs = {
+        let audio_manager = audio_manager.read().await;
+        audio_manager
+            .search(query, limit)
+            .await
+            .map_err(CommandError::LinkHandling)?
+    };
+    if songs.is_empty() {
+        return Err(CommandError::NoSearchResults(query.to_string()));
     }
+    Ok(songs)
 }
 
 pub async fn remove(
     queue_manager: Arc<RwLock<DiscordQueueManager>>,
-    index: usize,
-) -> Result<Box<dyn Song>, CommandError> {
+    range: QueueRange,
+) -> Result<Vec<QueueEntry>, CommandError> {
     let queue_manager = queue_manager.write().await;
     queue_manager
-        .remove_from_queue_by_index(index)
+        .remove_from_queue_by_range(range.indices())
         .await
-        .ok_or(CommandError::InvalidIndex(index))
+        .ok_or(CommandError::InvalidIndex(range.end))
+}
+pub async fn remove_requested_by(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    user_id: u64,
+) -> Result<Vec<QueueEntry>, CommandError> {
+    let queue_manager = queue_manager.write().await;
+    Ok(queue_manager.remove_requested_by(user_id).await)
+}
+pub async fn remove_duplicates(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+) -> Result<Vec<QueueEntry>, CommandError> {
+    let queue_manager = queue_manager.write().await;
+    Ok(queue_manager.remove_duplicates().await)
 }
 
 pub async fn clear(queue_manager: Arc<RwLock<DiscordQueueManager>>) -> Result<(), CommandError> {
@@ -200,12 +457,28 @@ pub async fn move_song(
 ) -> Result<(), CommandError> {
     let queue_manager = queue_manager.write().await;
     queue_manager
-        .swap(from, to)
+        .move_song(from, to)
         .await
-        .map_err(|e| CommandError::InvalidIndex(e))?;
+        .map_err(|i| CommandError::InvalidIndex(i + 1))?;
     Ok(())
 }
 
+/// Skips to the song at the 1-based position
+pub async fn jump(
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    position: usize,
+) -> Result<Box<dyn Song>, CommandError> {
+    // Prefix commands don't enforce the minimum of the parameter
+    if position == 0 {
+        return Err(CommandError::InvalidRange(position.to_string()));
+    }
+    let queue_manager = queue_manager.write().await;
+    queue_manager
+        .jump(position - 1)
+        .await?
+        .ok_or(CommandError::InvalidIndex(position))
+}
+
 pub async fn save(
     queue_manager: Arc<RwLock<DiscordQueueManager>>,
     name: &String,
@@ -249,32 +522,49 @@ pub async fn saved(
     Ok(embeds)
 }
 
+/// Gets the songs of saved entries without downloading them, entries without a requester get
+/// the default one
+pub async fn resolve_saved_entries(
+    audio_manager: &Arc<RwLock<DiscordAudioManager>>,
+    saved_entries: Vec<SavedEntry>,
+    default_requester: Option<Requester>,
+) -> (Vec<QueueEntry>, ResolvedLink) {
+    let mut audio_manager = audio_manager.write().await;
+    let mut entries = vec![];
+    let mut resolved = ResolvedLink::default();
+    for saved in saved_entries {
+        let requester = saved.requester.or(default_requester);
+        if let Ok(link) = audio_manager.resolve_link(&saved.id).await {
+            entries.extend(
+                link.songs
+                    .iter()
+                    .map(|song| QueueEntry::new(song.clone_song(), requester)),
+            );
+            resolved.append(link);
+        }
+    }
+    (entries, resolved)
+}
+
 pub async fn load(
     queue_manager: Arc<RwLock<DiscordQueueManager>>,
     audio_manager: Arc<RwLock<DiscordAudioManager>>,
     name: &String,
-) -> Result<usize, CommandError> {
+    requester: u64,
+    position: QueuePosition,
+) -> Result<Added, CommandError> {
     let saved_queue = {
         let queue_manager = queue_manager.read().await;
         queue_manager
             .get_saved_queue(name)
             .ok_or(CommandError::EmptyQueue)?
     };
-    let songs = {
-        let mut audio_manager = audio_manager.write().await;
-        let mut res = vec![];
-        for s in saved_queue {
-            let song = audio_manager.handle_link(&s).await;
-            if let Ok(mut song) = song {
-                res.append(&mut song);
-            }
-        }
-        res
-    };
-    let n = songs.len();
-    let queue_manager = queue_manager.write().await;
-    queue_manager.add_to_queue(songs).await?;
-    Ok(n)
+    let (songs, resolved) =
+        resolve_saved_entries(&audio_manager, saved_queue, Some(Requester::new(requester))).await;
+    let added = add_within_limits(&queue_manager, songs, position).await?;
+    audio_manager.read().await.cache(resolved, &added.ids);
+    prioritize_downloads(&queue_manager, &audio_manager).await;
+    Ok(added)
 }
 
 pub async fn remove_saved(
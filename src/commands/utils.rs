@@ -1,16 +1,74 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
-use serenity::all::GuildId;
+use serenity::all::{GuildId, Permissions};
 use tokio::sync::RwLock;
 
-use crate::common::{
-    CommandError, Context, DataRegistryError, DiscordAudioManager, DiscordQueueManager,
+use crate::{
+    audio_manager::LocalLibrary,
+    common::{
+        CommandError, Context, DataRegistryError, DiscordAudioManager, DiscordCacheManager,
+        DiscordQueueManager,
+    },
 };
 
 static _PROGRESS_BAR_LENGTH: usize = 20;
 static _PROGRESS_BAR_FILL: &str = "▮";
 static _PROGRESS_BAR_EMPTY: &str = "▯";
 
+/// Returns the users in the bot's voice channel, other bots are not counted.
+/// `None` if the bot isn't in a voice channel
+pub fn get_listeners(ctx: Context<'_>) -> Option<HashSet<u64>> {
+    let bot_id = ctx.cache().current_user().id;
+    let guild = ctx.guild()?;
+    let channel_id = guild
+        .voice_states
+        .get(&bot_id)
+        .and_then(|voice_state| voice_state.channel_id)?;
+    Some(
+        guild
+            .voice_states
+            .values()
+            .filter(|voice_state| voice_state.channel_id == Some(channel_id))
+            .filter(|voice_state| !voice_state.member.as_ref().is_some_and(|m| m.user.bot))
+            .filter(|voice_state| voice_state.user_id != bot_id)
+            .map(|voice_state| voice_state.user_id.get())
+            .collect(),
+    )
+}
+
+/// Returns the role ids of the author and their permissions in the channel of the command
+pub async fn get_author_roles(ctx: Context<'_>) -> (Vec<u64>, Permissions) {
+    let member = match ctx.author_member().await {
+        Some(member) => member,
+        None => return (vec![], Permissions::empty()),
+    };
+    let roles = member.roles.iter().map(|role| role.get()).collect();
+    // Members of interactions come with their permissions, prefix commands need the cache
+    let permissions = member.permissions.or_else(|| {
+        let guild = ctx.guild()?;
+        let channel = guild.channels.get(&ctx.channel_id())?;
+        Some(guild.user_permissions_in(channel, &member))
+    });
+    (roles, permissions.unwrap_or_default())
+}
+
+/// Whether the framework has a command or subcommand with this qualified name
+pub fn command_exists(ctx: Context<'_>, name: &str) -> bool {
+    let mut commands = ctx
+        .framework()
+        .options()
+        .commands
+        .iter()
+        .collect::<Vec<_>>();
+    while let Some(command) = commands.pop() {
+        if command.qualified_name == name {
+            return true;
+        }
+        commands.extend(command.subcommands.iter());
+    }
+    false
+}
+
 pub async fn get_queue_manager(
     ctx: Context<'_>,
     guild_id: &GuildId,
@@ -42,3 +100,25 @@ pub async fn get_audio_manager(
         ))
         .cloned()
 }
+
+pub async fn get_library(ctx: Context<'_>) -> Result<Arc<LocalLibrary>, CommandError> {
+    let context = ctx.serenity_context();
+    let data = context.data.read().await;
+    data.get::<LocalLibrary>()
+        .ok_or(CommandError::DataRegistry(
+            DataRegistryError::LibraryNotRegistered,
+        ))
+        .cloned()
+}
+
+pub async fn get_cache_manager(
+    ctx: Context<'_>,
+) -> Result<Arc<RwLock<DiscordCacheManager>>, CommandError> {
+    let context = ctx.serenity_context();
+    let data = context.data.read().await;
+    data.get::<DiscordCacheManager>()
+        .ok_or(CommandError::DataRegistry(
+            DataRegistryError::CacheManagerNotRegistered,
+        ))
+        .cloned()
+}
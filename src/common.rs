@@ -1,14 +1,18 @@
-use std::{fmt::Display, sync::Arc, collections::HashMap};
+use std::{collections::HashMap, fmt::Display, sync::Arc};
 
 use async_trait::async_trait;
 use serenity::all::GuildId;
-use songbird::{error::{ControlError, JoinError}, input::Input, typemap::TypeMapKey};
+use songbird::{
+    error::{ControlError, JoinError},
+    input::Input,
+    typemap::TypeMapKey,
+};
 use tokio::sync::RwLock;
 
 use crate::{
-    audio_manager::{AudioManager, StandardLinkHandler},
+    audio_manager::{AudioManager, LinkHandlerRegistry, LocalLibrary},
     cache_manager::{cache_saver::FileCacheSaver, CacheManager},
-    queue_manager::{FileQueueSaver, QueueManager},
+    queue_manager::{FileQueueSaver, QueueLimit, QueueManager},
 };
 
 pub type SongId = String;
@@ -18,23 +22,37 @@ pub trait Song: Send + Sync {
     fn title(&self) -> &String;
     fn artist(&self) -> &String;
     fn duration(&self) -> Option<u64>;
+    /// Whether the song is an endless stream
+    fn is_live(&self) -> bool {
+        false
+    }
+    /// The title currently announced by a live stream
+    async fn now_playing(&self) -> Option<String> {
+        None
+    }
+    /// Volume multiplier that brings the song to the target loudness
+    async fn gain(&self) -> Option<f32> {
+        None
+    }
     async fn get_input(&self) -> Input;
     fn clone_song(&self) -> Box<dyn Song>;
     fn get_id(&self) -> &SongId;
 }
 
 pub type DiscordCacheSaver = FileCacheSaver;
-pub type DiscordLinkHandler = StandardLinkHandler<DiscordCacheSaver>;
+pub type DiscordLinkHandler = LinkHandlerRegistry;
 pub type DiscordAudioManager = AudioManager<DiscordCacheSaver, DiscordLinkHandler>;
 pub type DiscordCacheManager = CacheManager<DiscordCacheSaver>;
 pub type DiscordQueueSaver = FileQueueSaver;
 pub type DiscordQueueManager = QueueManager<DiscordQueueSaver>;
 
+#[allow(dead_code)]
 pub struct Config {
     pub prefix: String,
     pub token: String,
     pub cache_dir: String,
     pub saved_queues_path: String,
+    pub rejoin_voice_channel: bool,
 }
 
 impl TypeMapKey for Config {
@@ -63,6 +81,9 @@ impl TypeMapKey for DiscordCacheManager {
     type Value = Arc<RwLock<DiscordCacheManager>>;
 }
 
+impl TypeMapKey for LocalLibrary {
+    type Value = Arc<LocalLibrary>;
+}
 
 #[derive(Debug)]
 pub enum CommandError {
@@ -71,13 +92,24 @@ pub enum CommandError {
     NotInVoiceChannel,
     NoSongPlaying,
     LinkHandling(String),
+    NoSearchResults(String),
+    NoSongProvided,
     InvalidIndex(usize),
+    InvalidSeekPosition(String),
+    InvalidRange(String),
+    InvalidFilter(String),
+    UnknownCommand(String),
+    UserQueueLimit(usize),
+    QueueFull(usize),
+    SongTooLong(u64),
+    PlaylistTooLarge(usize),
     EmptyQueue,
+    EmptyHistory,
     NotInGuild,
-    DataRegistry(DataRegistryError)
+    DataRegistry(DataRegistryError),
 }
 
-impl Display for CommandError{
+impl Display for CommandError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             CommandError::SerenityError(e) => write!(f, "Serenity error: {}", e),
@@ -85,8 +117,27 @@ impl Display for CommandError{
             CommandError::NotInVoiceChannel => write!(f, "Not in a voice channel"),
             CommandError::NoSongPlaying => write!(f, "No song is currently playing"),
             CommandError::LinkHandling(l) => write!(f, "Link handling error: {}", l),
+            CommandError::NoSearchResults(q) => write!(f, "No search results for: {}", q),
+            CommandError::NoSongProvided => {
+                write!(f, "Provide a link, a search query or an audio file")
+            }
             CommandError::InvalidIndex(i) => write!(f, "Invalid index: {}", i),
-            CommandError::EmptyQueue =>  write!(f, "Queue is empty"),
+            CommandError::InvalidSeekPosition(p) => write!(f, "Invalid seek position: {}", p),
+            CommandError::InvalidRange(r) => write!(f, "Invalid range: {}, use 3 or 3-10", r),
+            CommandError::InvalidFilter(e) => write!(f, "Invalid filter: {}", e),
+            CommandError::UnknownCommand(c) => write!(f, "Unknown command: {}", c),
+            CommandError::UserQueueLimit(n) => {
+                write!(f, "Each member can have at most {} songs in the queue", n)
+            }
+            CommandError::QueueFull(n) => write!(f, "The queue is limited to {} songs", n),
+            CommandError::SongTooLong(s) => {
+                write!(f, "Songs can be at most {}:{:02} long", s / 60, s % 60)
+            }
+            CommandError::PlaylistTooLarge(n) => {
+                write!(f, "A link can add at most {} songs", n)
+            }
+            CommandError::EmptyQueue => write!(f, "Queue is empty"),
+            CommandError::EmptyHistory => write!(f, "No songs have been played yet"),
             CommandError::NotInGuild => write!(f, "Not in a guild"),
             CommandError::DataRegistry(e) => write!(f, "Data registry error: {}", e),
         }
@@ -99,6 +150,17 @@ impl From<serenity::Error> for CommandError {
     }
 }
 
+impl From<QueueLimit> for CommandError {
+    fn from(limit: QueueLimit) -> Self {
+        match limit {
+            QueueLimit::UserSongs(n) => CommandError::UserQueueLimit(n),
+            QueueLimit::QueueLength(n) => CommandError::QueueFull(n),
+            QueueLimit::Duration(s) => CommandError::SongTooLong(s),
+            QueueLimit::PlaylistSize(n) => CommandError::PlaylistTooLarge(n),
+        }
+    }
+}
+
 impl From<ControlError> for CommandError {
     fn from(error: ControlError) -> Self {
         CommandError::SongbirdError(error.into())
@@ -115,7 +177,7 @@ impl Display for SongbirdError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             SongbirdError::JoinError(e) => write!(f, "Join error: {}", e),
-            SongbirdError::ControlError(e) => write!(f, "Control error: {}", e)
+            SongbirdError::ControlError(e) => write!(f, "Control error: {}", e),
         }
     }
 }
@@ -133,18 +195,29 @@ impl From<ControlError> for SongbirdError {
 }
 
 #[derive(Debug)]
-pub enum DataRegistryError{
+#[allow(clippy::enum_variant_names)]
+pub enum DataRegistryError {
     QueueManagerNotRegistered,
     SongbirdNotRegistered,
     AudioManagerNotRegistered,
+    LibraryNotRegistered,
+    CacheManagerNotRegistered,
 }
 
 impl Display for DataRegistryError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            DataRegistryError::QueueManagerNotRegistered => write!(f, "Queue manager not registered"),
+            DataRegistryError::QueueManagerNotRegistered => {
+                write!(f, "Queue manager not registered")
+            }
             DataRegistryError::SongbirdNotRegistered => write!(f, "Songbird not registered"),
-            DataRegistryError::AudioManagerNotRegistered => write!(f, "Audio manager not registered"),
+            DataRegistryError::AudioManagerNotRegistered => {
+                write!(f, "Audio manager not registered")
+            }
+            DataRegistryError::LibraryNotRegistered => write!(f, "Local library not registered"),
+            DataRegistryError::CacheManagerNotRegistered => {
+                write!(f, "Cache manager not registered")
+            }
         }
     }
-}
\ No newline at end of file
+}
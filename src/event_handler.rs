@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use serenity::{
+    all::{ChannelId, GuildId},
     async_trait,
     client::{Context, EventHandler},
     model::{gateway::Ready, guild::Guild},
@@ -9,7 +10,11 @@ use tokio::sync::RwLock;
 use tracing::Level;
 
 use crate::{
-    common::{DiscordQueueManager, DiscordQueueSaver},
+    commands::bot,
+    common::{
+        DiscordAudioManager, DiscordCacheManager, DiscordQueueManager, DiscordQueueSaver, SongId,
+    },
+    queue_manager::QueueEntry,
     Config,
 };
 
@@ -40,11 +45,92 @@ impl EventHandler for Handler {
                 }
             };
             let queue_saver = DiscordQueueSaver::new(&p);
-            queue_manager_map.write().await.insert(
-                guild_id,
-                Arc::new(RwLock::new(DiscordQueueManager::new(queue_saver))),
-            );
+            let queue_manager = DiscordQueueManager::new(queue_saver);
+            let cache_manager = data
+                .get::<DiscordCacheManager>()
+                .expect("Cache manager not found")
+                .clone();
+            cache_manager
+                .write()
+                .await
+                .protect(queue_manager.songs_in_use());
+            queue_manager
+                .set_play_listener(Arc::new(move |id: &SongId| {
+                    let cache_manager = cache_manager.clone();
+                    let id = id.clone();
+                    tokio::spawn(async move { cache_manager.write().await.touch(&id) });
+                }))
+                .await;
+            let queue_manager = Arc::new(RwLock::new(queue_manager));
+            queue_manager_map
+                .write()
+                .await
+                .insert(guild_id, queue_manager.clone());
             tracing::event!(Level::INFO, "Queue manager created for guild {}", guild_id);
+
+            let rejoin = config.rejoin_voice_channel;
+            let audio_manager = data
+                .get::<DiscordAudioManager>()
+                .expect("Audio manager not found")
+                .clone();
+            drop(data);
+            restore_queue_state(&ctx, guild_id, queue_manager, audio_manager, rejoin).await;
+        }
+    }
+}
+
+async fn restore_queue_state(
+    ctx: &Context,
+    guild_id: GuildId,
+    queue_manager: Arc<RwLock<DiscordQueueManager>>,
+    audio_manager: Arc<RwLock<DiscordAudioManager>>,
+    rejoin: bool,
+) {
+    let state = match queue_manager.read().await.load_state() {
+        Some(state) => state,
+        None => return,
+    };
+    tracing::event!(Level::INFO, "Restoring queue state for guild {}", guild_id);
+    let (current_song, songs) = {
+        let mut audio_manager = audio_manager.write().await;
+        let mut current_song = None;
+        if let Some(saved) = &state.current_song {
+            if let Ok(mut song) = audio_manager.handle_link(&saved.id).await {
+                current_song = song
+                    .drain(..)
+                    .next()
+                    .map(|song| QueueEntry::new(song, saved.requester));
+            }
+        }
+        let mut songs = vec![];
+        for saved in &state.queue {
+            if let Ok(song) = audio_manager.handle_link(&saved.id).await {
+                songs.extend(
+                    song.into_iter()
+                        .map(|song| QueueEntry::new(song, saved.requester)),
+                );
+            }
+        }
+        (current_song, songs)
+    };
+    queue_manager
+        .read()
+        .await
+        .restore_state(&state, current_song, songs)
+        .await;
+
+    let channel_id = match state.channel_id {
+        Some(channel_id) if rejoin => ChannelId::new(channel_id),
+        _ => return,
+    };
+    let manager = match songbird::get(ctx).await {
+        Some(manager) => manager,
+        None => {
+            tracing::event!(Level::ERROR, "Songbird not registered");
+            return;
         }
+    };
+    if let Err(e) = bot::join(channel_id, guild_id, manager, queue_manager).await {
+        tracing::event!(Level::ERROR, "Failed to rejoin voice channel: {}", e);
     }
 }
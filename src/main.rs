@@ -5,14 +5,18 @@ mod common;
 mod event_handler;
 mod queue_manager;
 
-use audio_manager::StandardLinkHandler;
+use audio_manager::{
+    read_metadata, DownloadConfig, HttpLinkHandler, LinkHandlerRegistry, LocalFileLinkHandler,
+    LocalLibrary, StandardLinkHandler,
+};
 
+use cache_manager::CacheLimits;
 use common::{DiscordAudioManager, DiscordQueueManager};
 use dotenv::dotenv;
 use poise::PrefixFrameworkOptions;
 use tracing::event;
 
-use std::{collections::HashMap, env, sync::Arc};
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
 use tokio::{
     signal::unix::{signal, SignalKind},
     sync::RwLock,
@@ -20,13 +24,18 @@ use tokio::{
 
 use songbird::SerenityInit;
 
-use serenity::{client::Client, prelude::GatewayIntents};
+use serenity::{
+    client::Client,
+    prelude::{GatewayIntents, TypeMap},
+};
 
 use crate::{
     common::{CommandError, Config, Data, DiscordCacheManager, DiscordCacheSaver},
     event_handler::Handler,
 };
 
+const QUEUE_STATE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
@@ -36,6 +45,29 @@ async fn main() {
     let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
     let prefix = env::var("DISCORD_PREFIX").unwrap_or_else(|_e| "!".to_string());
     let cache_dir = env::var("DISCORD_CACHE_DIR").unwrap_or_else(|_e| "./cache".to_string());
+    // Streams need the program as a `&'static str`, it lives as long as the bot
+    let yt_dlp_path: &'static str = env::var("DISCORD_YT_DLP_PATH")
+        .unwrap_or_else(|_e| "yt-dlp".to_string())
+        .leak();
+    let library_dir = env::var("DISCORD_LIBRARY_DIR").ok();
+    let cache_limits = CacheLimits {
+        max_size: env::var("DISCORD_CACHE_MAX_SIZE")
+            .ok()
+            .and_then(|s| s.parse().ok()),
+        max_files: env::var("DISCORD_CACHE_MAX_FILES")
+            .ok()
+            .and_then(|s| s.parse().ok()),
+    };
+    let mut download_config = DownloadConfig::default();
+    if let Some(concurrency) = env::var("DISCORD_DOWNLOAD_CONCURRENCY")
+        .ok()
+        .and_then(|s| s.parse().ok())
+    {
+        download_config.concurrency = concurrency;
+    }
+    let rejoin_voice_channel = env::var("DISCORD_REJOIN_VOICE_CHANNEL")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
 
     let framework: poise::Framework<Data, CommandError> = poise::Framework::builder()
         .options(poise::FrameworkOptions {
@@ -46,20 +78,39 @@ async fn main() {
                 commands::pause(),
                 commands::resume(),
                 commands::skip(),
+                commands::voteskip(),
+                commands::djrole(),
+                commands::previous(),
+                commands::seek(),
+                commands::volume(),
+                commands::crossfade(),
+                commands::filter(),
                 commands::set_loop(),
                 commands::shuffle(),
+                commands::fair(),
+                commands::limits(),
                 commands::show(),
                 commands::queue(),
+                commands::history(),
                 commands::add(),
+                commands::insert(),
+                commands::playnext(),
+                commands::playnow(),
+                commands::add_message_attachments(),
                 commands::remove(),
+                commands::jump(),
                 commands::clear(),
                 commands::move_song(),
                 commands::save(),
                 commands::saved(),
                 commands::load(),
                 commands::remove_saved(),
+                commands::library(),
+                commands::permission(),
+                commands::check_cache(),
                 commands::help(),
             ],
+            command_check: Some(|ctx| Box::pin(commands::command_check(ctx))),
             prefix_options: PrefixFrameworkOptions {
                 prefix: Some(prefix.clone()),
                 ..Default::default()
@@ -70,7 +121,7 @@ async fn main() {
             Box::pin(async move {
                 poise::builtins::register_globally(ctx, &framework.options().commands)
                     .await
-                    .map_err(|e| CommandError::SerenityError(e))?;
+                    .map_err(CommandError::SerenityError)?;
                 let create_commands =
                     poise::builtins::create_application_commands(&framework.options().commands);
 
@@ -96,16 +147,45 @@ async fn main() {
             token: token.clone(),
             cache_dir: cache_dir.clone(),
             saved_queues_path: cache_dir.clone(),
+            rejoin_voice_channel,
         });
         data.insert::<DiscordQueueManager>(Arc::new(RwLock::new(HashMap::new())));
-        let mut cache_manager = DiscordCacheManager::new(DiscordCacheSaver::new(cache_dir.clone()));
+        let mut cache_manager = DiscordCacheManager::new(DiscordCacheSaver::new(cache_dir.clone()))
+            .with_limits(&cache_dir, cache_limits);
         cache_manager.load_cache();
+        let report = cache_manager.check_integrity(|p| read_metadata(p).is_some());
+        event!(
+            tracing::Level::INFO,
+            "Checked cache integrity: {:?}",
+            report
+        );
+        cache_manager.evict();
         let arc_cache_manager = Arc::new(RwLock::new(cache_manager));
         data.insert::<DiscordCacheManager>(arc_cache_manager.clone());
-        data.insert::<DiscordAudioManager>(Arc::new(RwLock::new(DiscordAudioManager::new(
-            arc_cache_manager.clone(),
-            StandardLinkHandler::new(cache_dir, arc_cache_manager.clone()),
-        ))));
+        let mut link_handler = LinkHandlerRegistry::new()
+            .register(
+                StandardLinkHandler::youtube(&cache_dir, arc_cache_manager.clone())
+                    .with_yt_dlp_path(yt_dlp_path),
+            )
+            .register(
+                StandardLinkHandler::soundcloud(&cache_dir, arc_cache_manager.clone())
+                    .with_yt_dlp_path(yt_dlp_path),
+            )
+            .register(
+                StandardLinkHandler::bandcamp(&cache_dir, arc_cache_manager.clone())
+                    .with_yt_dlp_path(yt_dlp_path),
+            )
+            .register(HttpLinkHandler::new());
+        if let Some(library_dir) = library_dir {
+            let library = Arc::new(LocalLibrary::index(library_dir));
+            link_handler = link_handler.register(LocalFileLinkHandler::new(library.clone()));
+            data.insert::<LocalLibrary>(library);
+        }
+        data.insert::<DiscordAudioManager>(Arc::new(RwLock::new(
+            DiscordAudioManager::new(arc_cache_manager.clone(), link_handler, &cache_dir)
+                .with_download_config(download_config)
+                .with_yt_dlp_path(yt_dlp_path),
+        )));
     }
     let data = client.data.clone();
     tokio::spawn(async move {
@@ -116,6 +196,21 @@ async fn main() {
             .map_err(|why| println!("Client ended: {:?}", why));
     });
 
+    let state_data = data.clone();
+    tokio::spawn(async move {
+        let start = tokio::time::Instant::now() + QUEUE_STATE_SAVE_INTERVAL;
+        let mut interval = tokio::time::interval_at(start, QUEUE_STATE_SAVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let data = state_data.read().await;
+            save_queue_states(&data).await;
+            // Eviction is skipped while a queue is busy, so it is retried here as well
+            if let Some(cache_manager) = data.get::<DiscordCacheManager>() {
+                cache_manager.write().await.evict();
+            }
+        }
+    });
+
     let mut sigint = signal(SignalKind::interrupt()).expect("Failed to create SIGINT signal");
     let mut sigterm = signal(SignalKind::terminate()).expect("Failed to create SIGTERM signal");
     tokio::select! {
@@ -147,5 +242,18 @@ async fn main() {
             queue_manager.save_queues();
         }
     }
+    event!(tracing::Level::INFO, "Saving queue states");
+    save_queue_states(&data).await;
     event!(tracing::Level::INFO, "Received Ctrl-C, shutting down.");
 }
+
+async fn save_queue_states(data: &TypeMap) {
+    let queue_managers = data
+        .get::<DiscordQueueManager>()
+        .expect("Queue manager not found")
+        .read()
+        .await;
+    for queue_manager in queue_managers.values() {
+        queue_manager.read().await.save_state().await;
+    }
+}
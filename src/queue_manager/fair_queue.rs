@@ -0,0 +1,135 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::QueueEntry;
+
+/// Reorders the queue so that requesters take turns, each requester's songs keep their order.
+/// Requesters are ordered by their first song in the queue and the turn of `last_requester`,
+/// who requested the current song, comes last
+pub fn fair_order(
+    queue: VecDeque<QueueEntry>,
+    last_requester: Option<u64>,
+) -> VecDeque<QueueEntry> {
+    let mut turns: Vec<(Option<u64>, VecDeque<QueueEntry>)> = vec![];
+    for entry in queue {
+        let requester = entry.requester.map(|r| r.user_id);
+        match turns.iter_mut().find(|(r, _)| *r == requester) {
+            Some((_, songs)) => songs.push_back(entry),
+            None => turns.push((requester, VecDeque::from([entry]))),
+        }
+    }
+    if let Some(i) = turns
+        .iter()
+        .position(|(r, _)| r.is_some() && *r == last_requester)
+    {
+        turns.rotate_left(i + 1);
+    }
+    let mut res = VecDeque::new();
+    while !turns.is_empty() {
+        for (_, songs) in turns.iter_mut() {
+            if let Some(entry) = songs.pop_front() {
+                res.push_back(entry);
+            }
+        }
+        turns.retain(|(_, songs)| !songs.is_empty());
+    }
+    res
+}
+
+/// Inserts the entry at the end of its requester's next turn, the queued songs keep their order.
+/// The n-th song of a requester plays in turn n and `last_requester`, who requested the
+/// current song, has used their first turn already
+pub fn fair_insert(
+    queue: &mut VecDeque<QueueEntry>,
+    entry: QueueEntry,
+    last_requester: Option<u64>,
+) {
+    let requester_of = |entry: &QueueEntry| entry.requester.map(|r| r.user_id);
+    let turn = |requester: Option<u64>, songs: usize| {
+        songs + usize::from(requester.is_some() && requester == last_requester)
+    };
+    let mut songs: HashMap<Option<u64>, usize> = HashMap::new();
+    let turns = queue
+        .iter()
+        .map(|entry| {
+            let requester = requester_of(entry);
+            let count = songs.entry(requester).or_default();
+            *count += 1;
+            turn(requester, *count)
+        })
+        .collect::<Vec<_>>();
+    let requester = requester_of(&entry);
+    let own_turn = turn(
+        requester,
+        songs.get(&requester).copied().unwrap_or_default() + 1,
+    );
+    let after_own = queue
+        .iter()
+        .rposition(|e| requester_of(e) == requester)
+        .map_or(0, |i| i + 1);
+    let index = (after_own..queue.len())
+        .find(|&i| turns[i] > own_turn)
+        .unwrap_or(queue.len());
+    queue.insert(index, entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{audio_manager::test_utils::FakeSong, queue_manager::Requester};
+
+    fn entry(id: &str, requester: u64) -> QueueEntry {
+        let song = FakeSong::new(id, 0, Default::default());
+        QueueEntry::new(Box::new(song), Some(Requester::new(requester)))
+    }
+
+    fn ids(queue: &VecDeque<QueueEntry>) -> Vec<&str> {
+        queue.iter().map(|e| e.song.get_id().as_str()).collect()
+    }
+
+    #[test]
+    fn test_fair_order() {
+        let queue = VecDeque::from([
+            entry("a1", 1),
+            entry("a2", 1),
+            entry("a3", 1),
+            entry("b1", 2),
+            entry("c1", 3),
+            entry("b2", 2),
+        ]);
+        let queue = fair_order(queue, None);
+        assert_eq!(ids(&queue), ["a1", "b1", "c1", "a2", "b2", "a3"]);
+
+        // The requester of the current song goes last
+        let queue = fair_order(queue, Some(1));
+        assert_eq!(ids(&queue), ["b1", "c1", "a1", "b2", "a2", "a3"]);
+
+        // Playing the next song keeps the order fair
+        let mut queue = queue;
+        queue.pop_front();
+        let queue = fair_order(queue, Some(2));
+        assert_eq!(ids(&queue), ["c1", "a1", "b2", "a2", "a3"]);
+    }
+
+    #[test]
+    fn test_fair_insert() {
+        // Manually ordered songs stay where they are
+        let mut queue = VecDeque::from([entry("b1", 2), entry("a1", 1), entry("c1", 3)]);
+        fair_insert(&mut queue, entry("b2", 2), None);
+        assert_eq!(ids(&queue), ["b1", "a1", "c1", "b2"]);
+
+        fair_insert(&mut queue, entry("a2", 1), None);
+        assert_eq!(ids(&queue), ["b1", "a1", "c1", "b2", "a2"]);
+
+        // A new requester gets a turn before those who had one
+        fair_insert(&mut queue, entry("d1", 4), None);
+        assert_eq!(ids(&queue), ["b1", "a1", "c1", "d1", "b2", "a2"]);
+
+        // The requester of the current song has used their first turn
+        let mut queue = VecDeque::from([entry("b1", 2), entry("b2", 2)]);
+        fair_insert(&mut queue, entry("a1", 1), None);
+        assert_eq!(ids(&queue), ["b1", "a1", "b2"]);
+        let mut queue = VecDeque::from([entry("b1", 2), entry("b2", 2)]);
+        fair_insert(&mut queue, entry("a1", 1), Some(1));
+        assert_eq!(ids(&queue), ["b1", "b2", "a1"]);
+    }
+}
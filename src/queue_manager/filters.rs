@@ -0,0 +1,613 @@
+use std::{
+    f64::consts::PI,
+    fmt::Display,
+    io::{self, ErrorKind, Read, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use poise::ChoiceParameter;
+use serde::{Deserialize, Serialize};
+use songbird::input::{
+    codecs::{CODEC_REGISTRY, PROBE},
+    Input, LiveInput, Parsed, RawAdapter,
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    errors::Error,
+    formats::{SeekMode, SeekTo},
+    io::MediaSource,
+    units::Time,
+};
+use tracing::{event, Level};
+
+use crate::audio_manager::Biquad;
+
+/// Center frequencies of the equalizer bands in Hz
+pub const EQ_BANDS: [f64; 5] = [60.0, 250.0, 1000.0, 4000.0, 12000.0];
+pub const NIGHTCORE_SPEED: f32 = 1.25;
+pub const VAPORWAVE_SPEED: f32 = 0.8;
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 2.0;
+pub const DEFAULT_BASS_BOOST: f32 = 6.0;
+pub const MAX_BASS_BOOST: f32 = 20.0;
+pub const MAX_EQ_GAIN: f32 = 12.0;
+const BASS_BOOST_FREQUENCY: f64 = 100.0;
+const EQ_Q: f64 = 1.0;
+/// Length of the header the raw adapter puts in front of the samples
+const RAW_HEADER_LENGTH: u64 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, ChoiceParameter)]
+pub enum FilterKind {
+    Nightcore,
+    Vaporwave,
+    Speed,
+    #[name = "Bass boost"]
+    BassBoost,
+    #[name = "EQ"]
+    Eq,
+    Mono,
+    Clear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ChoiceParameter)]
+pub enum EqBand {
+    #[name = "60 Hz"]
+    Sub,
+    #[name = "250 Hz"]
+    Bass,
+    #[name = "1 kHz"]
+    Mid,
+    #[name = "4 kHz"]
+    Presence,
+    #[name = "12 kHz"]
+    Treble,
+}
+
+/// Effects applied to the decoded audio of every song
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Filters {
+    /// Playback speed, the song is resampled so the pitch changes with it
+    pub speed: f32,
+    /// Gain of a low shelf below 100Hz in dB
+    pub bass_boost: f32,
+    /// Gain of each band in `EQ_BANDS` in dB
+    pub eq: [f32; 5],
+    /// Mixes all channels down to the same signal
+    pub mono: bool,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Filters {
+            speed: 1.0,
+            bass_boost: 0.0,
+            eq: [0.0; 5],
+            mono: false,
+        }
+    }
+}
+
+impl Filters {
+    pub fn is_active(&self) -> bool {
+        *self != Filters::default()
+    }
+
+    /// Applies a filter, `value` is the speed for `Speed`
+    /// and the gain in dB for `BassBoost` and `Eq`. Presets and mono are toggled
+    pub fn apply(
+        &mut self,
+        kind: FilterKind,
+        value: Option<f32>,
+        band: Option<EqBand>,
+    ) -> Result<(), String> {
+        if value.is_some_and(|v| !v.is_finite()) {
+            return Err("The value has to be a number".to_string());
+        }
+        match kind {
+            FilterKind::Nightcore => self.speed = toggle_speed(self.speed, NIGHTCORE_SPEED),
+            FilterKind::Vaporwave => self.speed = toggle_speed(self.speed, VAPORWAVE_SPEED),
+            FilterKind::Speed => {
+                let speed = value.ok_or("Provide a speed")?;
+                self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+            }
+            FilterKind::BassBoost => {
+                let gain = value.unwrap_or(DEFAULT_BASS_BOOST);
+                self.bass_boost = gain.clamp(0.0, MAX_BASS_BOOST);
+            }
+            FilterKind::Eq => {
+                let band = band.ok_or("Provide an EQ band")?;
+                let gain = value.ok_or("Provide a gain in dB")?;
+                self.eq[band as usize] = gain.clamp(-MAX_EQ_GAIN, MAX_EQ_GAIN);
+            }
+            FilterKind::Mono => self.mono = !self.mono,
+            FilterKind::Clear => *self = Filters::default(),
+        }
+        Ok(())
+    }
+}
+
+fn toggle_speed(current: f32, preset: f32) -> f32 {
+    if current == preset {
+        1.0
+    } else {
+        preset
+    }
+}
+
+impl Display for Filters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut active = vec![];
+        if self.speed != 1.0 {
+            active.push(format!("speed {}x", self.speed));
+        }
+        if self.bass_boost != 0.0 {
+            active.push(format!("bass boost {}dB", self.bass_boost));
+        }
+        for (frequency, gain) in EQ_BANDS.iter().zip(self.eq) {
+            if gain != 0.0 {
+                active.push(format!("EQ {}Hz {:+}dB", frequency, gain));
+            }
+        }
+        if self.mono {
+            active.push("mono".to_string());
+        }
+        match active.is_empty() {
+            true => write!(f, "None"),
+            false => write!(f, "{}", active.join(", ")),
+        }
+    }
+}
+
+/// Low shelf from the audio EQ cookbook with a shelf slope of 1
+fn low_shelf(frequency: f64, gain: f64, sample_rate: f64) -> Biquad {
+    let a = 10f64.powf(gain / 40.0);
+    let w0 = 2.0 * PI * frequency / sample_rate;
+    let (sin, cos) = w0.sin_cos();
+    let alpha = sin / 2.0 * 2f64.sqrt();
+    let sqrt_a = 2.0 * a.sqrt() * alpha;
+    let a0 = (a + 1.0) + (a - 1.0) * cos + sqrt_a;
+    Biquad::new(
+        [
+            a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a) / a0,
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos) / a0,
+            a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a) / a0,
+        ],
+        [
+            1.0,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos) / a0,
+            ((a + 1.0) + (a - 1.0) * cos - sqrt_a) / a0,
+        ],
+    )
+}
+
+/// Peaking EQ from the audio EQ cookbook
+fn peaking(frequency: f64, gain: f64, sample_rate: f64) -> Biquad {
+    let a = 10f64.powf(gain / 40.0);
+    let w0 = 2.0 * PI * frequency / sample_rate;
+    let (sin, cos) = w0.sin_cos();
+    let alpha = sin / (2.0 * EQ_Q);
+    let a0 = 1.0 + alpha / a;
+    Biquad::new(
+        [
+            (1.0 + alpha * a) / a0,
+            -2.0 * cos / a0,
+            (1.0 - alpha * a) / a0,
+        ],
+        [1.0, -2.0 * cos / a0, (1.0 - alpha / a) / a0],
+    )
+}
+
+/// Filters of one channel that don't change the number of samples
+fn channel_filters(filters: &Filters, sample_rate: f64) -> Vec<Biquad> {
+    let mut res = vec![];
+    if filters.bass_boost != 0.0 {
+        res.push(low_shelf(
+            BASS_BOOST_FREQUENCY,
+            filters.bass_boost as f64,
+            sample_rate,
+        ));
+    }
+    for (frequency, gain) in EQ_BANDS.iter().zip(filters.eq) {
+        // Bands above the Nyquist frequency can't be represented
+        if gain != 0.0 && *frequency < sample_rate / 2.0 {
+            res.push(peaking(*frequency, gain as f64, sample_rate));
+        }
+    }
+    res
+}
+
+/// Filters processing interleaved frames, built from a snapshot of the filter settings
+struct FilterChain {
+    filters: Filters,
+    biquads: Vec<Vec<Biquad>>,
+}
+
+impl FilterChain {
+    fn new(filters: Filters, sample_rate: u32, channels: usize) -> Self {
+        FilterChain {
+            biquads: vec![channel_filters(&filters, sample_rate as f64); channels],
+            filters,
+        }
+    }
+
+    fn process(&mut self, frame: &mut [f32]) {
+        if self.filters.mono {
+            let mean = frame.iter().sum::<f32>() / frame.len() as f32;
+            frame.fill(mean);
+        }
+        for (sample, biquads) in frame.iter_mut().zip(self.biquads.iter_mut()) {
+            let filtered = biquads
+                .iter_mut()
+                .fold(*sample as f64, |s, biquad| biquad.process(s));
+            *sample = (filtered as f32).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+/// Position of a filtered track in the song. Songbird counts the time that was played,
+/// which runs faster or slower than the song when the speed is changed
+#[derive(Clone, Debug, Default)]
+pub struct SongPosition(Arc<AtomicU64>);
+
+impl SongPosition {
+    pub fn get(&self) -> Duration {
+        Duration::from_micros(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, seconds: f64) {
+        self.0
+            .store((seconds.max(0.0) * 1_000_000.0) as u64, Ordering::Relaxed);
+    }
+}
+
+/// Decodes a parsed input and applies the filters to it,
+/// the output is raw interleaved f32 samples to be played through a `RawAdapter`.
+/// The filters are read again for every packet so changes apply to songs that are playing
+pub struct FilteredSource {
+    parsed: Parsed,
+    filters: Arc<Mutex<Filters>>,
+    chain: FilterChain,
+    sample_rate: u32,
+    channels: usize,
+    buffer: Option<SampleBuffer<f32>>,
+    /// Decoded frames that haven't been resampled yet
+    decoded: Vec<f32>,
+    /// Position of the next output frame in `decoded`, in frames
+    position: f64,
+    /// Frame of the song at the start of `decoded`
+    decoded_start: u64,
+    song_position: SongPosition,
+    output: Vec<u8>,
+    output_position: usize,
+}
+
+impl FilteredSource {
+    pub fn new(parsed: Parsed, filters: Arc<Mutex<Filters>>) -> Option<Self> {
+        let params = parsed.decoder.codec_params();
+        let sample_rate = params.sample_rate?;
+        let channels = params.channels?.count();
+        let chain = FilterChain::new(
+            filters.lock().expect("Filters lock was poisoned").clone(),
+            sample_rate,
+            channels,
+        );
+        Some(FilteredSource {
+            parsed,
+            filters,
+            chain,
+            sample_rate,
+            channels,
+            buffer: None,
+            decoded: vec![],
+            position: 0.0,
+            decoded_start: 0,
+            song_position: SongPosition::default(),
+            output: vec![],
+            output_position: 0,
+        })
+    }
+
+    /// Rebuilds the filter chain if the filters were changed
+    fn update_filters(&mut self) {
+        let filters = self.filters.lock().expect("Filters lock was poisoned");
+        if *filters != self.chain.filters {
+            self.chain = FilterChain::new(filters.clone(), self.sample_rate, self.channels);
+        }
+    }
+
+    /// Decodes the next packet into `decoded`, returns false at the end of the stream
+    fn decode_packet(&mut self) -> io::Result<bool> {
+        loop {
+            let packet = match self.parsed.format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(_)) => return Ok(false),
+                Err(e) => return Err(io::Error::other(e)),
+            };
+            if packet.track_id() != self.parsed.track_id {
+                continue;
+            }
+            let decoded = match self.parsed.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(Error::DecodeError(_)) => continue,
+                Err(e) => return Err(io::Error::other(e)),
+            };
+            if decoded.spec().channels.count() != self.channels {
+                return Err(io::Error::other("Channel count changed"));
+            }
+            let buffer = match &mut self.buffer {
+                Some(buffer) if buffer.capacity() >= decoded.capacity() * self.channels => buffer,
+                _ => self.buffer.insert(SampleBuffer::new(
+                    decoded.capacity() as u64,
+                    *decoded.spec(),
+                )),
+            };
+            buffer.copy_interleaved_ref(decoded);
+            self.decoded.extend_from_slice(buffer.samples());
+            return Ok(true);
+        }
+    }
+
+    /// Fills the output with the next filtered frames, returns false at the end of the stream
+    fn fill_output(&mut self) -> io::Result<bool> {
+        self.update_filters();
+        if !self.decode_packet()? {
+            return Ok(false);
+        }
+        let speed = self.chain.filters.speed as f64;
+        let frames = self.decoded.len() / self.channels;
+        self.output.clear();
+        self.output_position = 0;
+        let mut frame = vec![0.0; self.channels];
+        // Linear interpolation between the two frames around the position
+        while self.position + 1.0 < frames as f64 {
+            let index = self.position as usize;
+            let t = (self.position - index as f64) as f32;
+            let current = &self.decoded[index * self.channels..][..self.channels];
+            let next = &self.decoded[(index + 1) * self.channels..][..self.channels];
+            for ((sample, current), next) in frame.iter_mut().zip(current).zip(next) {
+                *sample = current + (next - current) * t;
+            }
+            self.chain.process(&mut frame);
+            for sample in &frame {
+                self.output.extend_from_slice(&sample.to_le_bytes());
+            }
+            self.position += speed;
+        }
+        let consumed = (self.position as usize).min(frames);
+        self.decoded.drain(..consumed * self.channels);
+        self.position -= consumed as f64;
+        self.decoded_start += consumed as u64;
+        self.song_position
+            .set((self.decoded_start as f64 + self.position) / self.sample_rate as f64);
+        Ok(true)
+    }
+}
+
+impl Read for FilteredSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.output_position >= self.output.len() {
+            if !self.fill_output()? {
+                return Ok(0);
+            }
+        }
+        let remaining = &self.output[self.output_position..];
+        let n = remaining.len().min(buf.len());
+        buf[..n].copy_from_slice(&remaining[..n]);
+        self.output_position += n;
+        Ok(n)
+    }
+}
+
+impl Seek for FilteredSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let SeekFrom::Start(pos) = pos else {
+            return Err(ErrorKind::Unsupported.into());
+        };
+        // The raw adapter passes on positions that still include its header
+        let frame_size = (std::mem::size_of::<f32>() * self.channels) as u64;
+        let frame = pos.saturating_sub(RAW_HEADER_LENGTH) / frame_size;
+        // Positions are in song time, `Player::seek` doesn't scale them by the speed
+        let seconds = frame as f64 / self.sample_rate as f64;
+        let seeked = self
+            .parsed
+            .format
+            .seek(
+                SeekMode::Coarse,
+                SeekTo::Time {
+                    time: Time::from(seconds),
+                    track_id: Some(self.parsed.track_id),
+                },
+            )
+            .map_err(io::Error::other)?;
+        // A coarse seek can land before the requested position
+        let seconds = match self.parsed.decoder.codec_params().time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(seeked.actual_ts);
+                time.seconds as f64 + time.frac
+            }
+            None => seconds,
+        };
+        self.decoded_start = (seconds * self.sample_rate as f64) as u64;
+        self.song_position.set(seconds);
+        self.parsed.decoder.reset();
+        self.decoded.clear();
+        self.position = 0.0;
+        self.output.clear();
+        self.output_position = 0;
+        self.chain = FilterChain::new(self.chain.filters.clone(), self.sample_rate, self.channels);
+        Ok(pos)
+    }
+}
+
+impl MediaSource for FilteredSource {
+    fn is_seekable(&self) -> bool {
+        self.parsed.supports_backseek
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Makes the input playable and wraps it so that the filters are applied to it,
+/// returns `None` if the input can't be decoded by the bot
+pub async fn filter_input(
+    input: Input,
+    filters: Arc<Mutex<Filters>>,
+) -> Option<(Input, SongPosition)> {
+    let input = match input.make_playable_async(&CODEC_REGISTRY, &PROBE).await {
+        Ok(input) => input,
+        Err(e) => {
+            event!(Level::WARN, "Failed to make input playable: {}", e);
+            return None;
+        }
+    };
+    let Input::Live(LiveInput::Parsed(parsed), _) = input else {
+        return None;
+    };
+    let source = FilteredSource::new(parsed, filters)?;
+    let (sample_rate, channels) = (source.sample_rate, source.channels as u32);
+    let song_position = source.song_position.clone();
+    Some((
+        RawAdapter::new(source, sample_rate, channels).into(),
+        song_position,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs};
+
+    use super::*;
+    use crate::{
+        audio_manager::test_utils::{cached_song, stereo_sine_wav},
+        cache_manager::CachedSong,
+        common::Song,
+    };
+
+    async fn filtered_source(song: &CachedSong, filters: Filters) -> FilteredSource {
+        let input = song.get_input().await;
+        let input = input
+            .make_playable_async(&CODEC_REGISTRY, &PROBE)
+            .await
+            .expect("Failed to make input playable");
+        let Input::Live(LiveInput::Parsed(parsed), _) = input else {
+            panic!("Input wasn't parsed");
+        };
+        FilteredSource::new(parsed, Arc::new(Mutex::new(filters)))
+            .expect("Failed to create filtered source")
+    }
+
+    fn read_samples(source: &mut FilteredSource) -> Vec<f32> {
+        let mut bytes = vec![];
+        source.read_to_end(&mut bytes).expect("Failed to read");
+        bytes
+            .chunks(4)
+            .map(|b| f32::from_le_bytes(b.try_into().expect("Partial sample")))
+            .collect()
+    }
+
+    async fn filtered_samples(song: &CachedSong, filters: Filters) -> Vec<f32> {
+        read_samples(&mut filtered_source(song, filters).await)
+    }
+
+    #[tokio::test]
+    async fn test_filtered_source() {
+        let tempdir = temp_dir().join("test_filtered_source");
+        fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        fs::write(tempdir.join("sine.wav"), stereo_sine_wav(1, 0.5)).expect("Failed to write song");
+        let song = cached_song("sine", tempdir.join("sine.wav"));
+
+        let unfiltered = filtered_samples(&song, Filters::default()).await;
+        let nightcore = filtered_samples(
+            &song,
+            Filters {
+                speed: NIGHTCORE_SPEED,
+                ..Default::default()
+            },
+        )
+        .await;
+        let ratio = unfiltered.len() as f32 / nightcore.len() as f32;
+        assert!((ratio - NIGHTCORE_SPEED).abs() < 0.01, "{}", ratio);
+
+        // The sine is only on the left channel
+        let mono = filtered_samples(
+            &song,
+            Filters {
+                mono: true,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(mono.len(), unfiltered.len());
+        for (frame, original) in mono.chunks(2).zip(unfiltered.chunks(2)) {
+            assert_eq!(frame[0], frame[1]);
+            assert!((frame[0] - (original[0] + original[1]) / 2.0).abs() < 1e-6);
+        }
+
+        // Seeking is in song time and the position follows the song, not the played time
+        let mut source = filtered_source(
+            &song,
+            Filters {
+                speed: 2.0,
+                ..Default::default()
+            },
+        )
+        .await;
+        let frame_size = 2 * std::mem::size_of::<f32>() as u64;
+        source
+            .seek(SeekFrom::Start(
+                RAW_HEADER_LENGTH + source.sample_rate as u64 / 2 * frame_size,
+            ))
+            .expect("Failed to seek");
+        let position = source.song_position.get().as_secs_f64();
+        assert!((position - 0.5).abs() < 0.05, "{}", position);
+        let samples = read_samples(&mut source);
+        let played = samples.len() as f64 / 2.0 / source.sample_rate as f64;
+        assert!((played - (1.0 - position) / 2.0).abs() < 0.01, "{}", played);
+        let position = source.song_position.get().as_secs_f64();
+        assert!((position - 1.0).abs() < 0.01, "{}", position);
+        fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+
+    #[test]
+    fn test_filters_apply() {
+        let mut filters = Filters::default();
+        assert!(!filters.is_active());
+        filters
+            .apply(FilterKind::Nightcore, None, None)
+            .expect("Failed to apply");
+        assert_eq!(filters.speed, NIGHTCORE_SPEED);
+        filters
+            .apply(FilterKind::Nightcore, None, None)
+            .expect("Failed to apply");
+        assert_eq!(filters.speed, 1.0);
+        filters
+            .apply(FilterKind::Speed, Some(5.0), None)
+            .expect("Failed to apply");
+        assert_eq!(filters.speed, MAX_SPEED);
+        assert!(filters.apply(FilterKind::Eq, Some(3.0), None).is_err());
+        filters
+            .apply(FilterKind::Eq, Some(3.0), Some(EqBand::Mid))
+            .expect("Failed to apply");
+        assert_eq!(filters.eq, [0.0, 0.0, 3.0, 0.0, 0.0]);
+        assert_eq!(filters.to_string(), "speed 2x, EQ 1000Hz +3dB");
+        filters
+            .apply(FilterKind::Clear, None, None)
+            .expect("Failed to apply");
+        assert_eq!(filters, Filters::default());
+        assert_eq!(filters.to_string(), "None");
+        assert!(filters
+            .apply(FilterKind::Speed, Some(f32::NAN), None)
+            .is_err());
+        assert!(filters
+            .apply(FilterKind::Eq, Some(f32::INFINITY), Some(EqBand::Mid))
+            .is_err());
+        assert_eq!(filters, Filters::default());
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use super::{QueueEntry, Requester};
+
+/// Limits on what can be added to the queue, 0 disables a limit
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct QueueLimits {
+    /// Songs each member can have in the queue
+    pub user_songs: usize,
+    /// Songs in the whole queue
+    pub queue_length: usize,
+    /// Length of a single song in seconds, live streams are not limited
+    pub duration: u64,
+    /// Songs a single link can add
+    pub playlist_size: usize,
+}
+
+/// The limit that kept songs out of the queue
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueueLimit {
+    UserSongs(usize),
+    QueueLength(usize),
+    Duration(u64),
+    PlaylistSize(usize),
+}
+
+/// The songs that fit in the queue, `limit` is the first limit that dropped songs
+pub struct LimitedEntries {
+    pub accepted: Vec<QueueEntry>,
+    pub dropped: usize,
+    pub limit: Option<QueueLimit>,
+}
+
+impl QueueLimits {
+    pub fn apply(&self, queue: &VecDeque<QueueEntry>, songs: Vec<QueueEntry>) -> LimitedEntries {
+        let total = songs.len();
+        let mut limit = None;
+        let mut hit = |l: QueueLimit| {
+            limit.get_or_insert(l);
+        };
+
+        let mut songs = songs;
+        if self.playlist_size > 0 && songs.len() > self.playlist_size {
+            songs.truncate(self.playlist_size);
+            hit(QueueLimit::PlaylistSize(self.playlist_size));
+        }
+
+        if self.duration > 0 {
+            let n = songs.len();
+            songs.retain(|e| {
+                e.song.is_live() || e.song.duration().is_none_or(|d| d <= self.duration)
+            });
+            if songs.len() < n {
+                hit(QueueLimit::Duration(self.duration));
+            }
+        }
+
+        if self.user_songs > 0 {
+            let mut queued = HashMap::new();
+            for entry in queue {
+                if let Some(requester) = entry.requester {
+                    *queued.entry(requester.user_id).or_insert(0) += 1;
+                }
+            }
+            let n = songs.len();
+            songs.retain(|e| match e.requester {
+                Some(requester) => {
+                    let count = queued.entry(requester.user_id).or_insert(0);
+                    *count += 1;
+                    *count <= self.user_songs
+                }
+                None => true,
+            });
+            if songs.len() < n {
+                hit(QueueLimit::UserSongs(self.user_songs));
+            }
+        }
+
+        if self.queue_length > 0 {
+            let free = self.queue_length.saturating_sub(queue.len());
+            if songs.len() > free {
+                songs.truncate(free);
+                hit(QueueLimit::QueueLength(self.queue_length));
+            }
+        }
+
+        LimitedEntries {
+            dropped: total - songs.len(),
+            accepted: songs,
+            limit,
+        }
+    }
+
+    /// How many more songs the requester can add and the limit that allows no more, the duration
+    /// isn't checked. `None` if the number isn't limited
+    pub fn remaining_songs(
+        &self,
+        queue: &VecDeque<QueueEntry>,
+        requester: Option<Requester>,
+    ) -> Option<(usize, QueueLimit)> {
+        let mut remaining: Option<(usize, QueueLimit)> = None;
+        let mut limit_to = |n: usize, limit: QueueLimit| {
+            if remaining.is_none_or(|(r, _)| n < r) {
+                remaining = Some((n, limit));
+            }
+        };
+        if self.playlist_size > 0 {
+            limit_to(
+                self.playlist_size,
+                QueueLimit::PlaylistSize(self.playlist_size),
+            );
+        }
+        if let (true, Some(requester)) = (self.user_songs > 0, requester) {
+            let queued = queue
+                .iter()
+                .filter(|e| e.requester.is_some_and(|r| r.user_id == requester.user_id))
+                .count();
+            limit_to(
+                self.user_songs.saturating_sub(queued),
+                QueueLimit::UserSongs(self.user_songs),
+            );
+        }
+        if self.queue_length > 0 {
+            limit_to(
+                self.queue_length.saturating_sub(queue.len()),
+                QueueLimit::QueueLength(self.queue_length),
+            );
+        }
+        remaining
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_manager::test_utils::FakeSong;
+
+    fn entry(id: &str, requester: u64, duration: u64) -> QueueEntry {
+        let song = FakeSong::new(id, 0, Default::default()).with_duration(duration);
+        QueueEntry::new(Box::new(song), Some(Requester::new(requester)))
+    }
+
+    fn ids(entries: &[QueueEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.song.get_id().as_str()).collect()
+    }
+
+    #[test]
+    fn test_queue_limits() {
+        let limits = QueueLimits {
+            user_songs: 3,
+            queue_length: 5,
+            duration: 600,
+            playlist_size: 4,
+        };
+        let queue = VecDeque::from([entry("a", 1, 60), entry("b", 2, 60)]);
+
+        // The playlist is cut to 4 songs, then the long song and user 1's third song are dropped
+        let songs = vec![
+            entry("c", 1, 60),
+            entry("long", 1, 601),
+            entry("d", 1, 60),
+            entry("e", 1, 60),
+            entry("f", 1, 60),
+        ];
+        let limited = limits.apply(&queue, songs);
+        assert_eq!(ids(&limited.accepted), ["c", "d"]);
+        assert_eq!(limited.dropped, 3);
+        assert_eq!(limited.limit, Some(QueueLimit::PlaylistSize(4)));
+
+        // Only one more song fits in the queue
+        let songs = vec![entry("g", 2, 60), entry("h", 2, 60)];
+        let queue = VecDeque::from([
+            entry("a", 1, 60),
+            entry("b", 2, 60),
+            entry("c", 1, 60),
+            entry("d", 1, 60),
+        ]);
+        let limited = limits.apply(&queue, songs);
+        assert_eq!(ids(&limited.accepted), ["g"]);
+        assert_eq!(limited.limit, Some(QueueLimit::QueueLength(5)));
+
+        let limited = limits.apply(&queue, vec![entry("long", 2, 700)]);
+        assert!(limited.accepted.is_empty());
+        assert_eq!(limited.limit, Some(QueueLimit::Duration(600)));
+
+        let limited = QueueLimits::default().apply(&queue, vec![entry("long", 2, 700)]);
+        assert_eq!(limited.dropped, 0);
+        assert_eq!(limited.limit, None);
+    }
+
+    #[test]
+    fn test_queue_limits_remaining() {
+        let limits = QueueLimits {
+            user_songs: 3,
+            queue_length: 5,
+            duration: 600,
+            playlist_size: 4,
+        };
+        let queue = VecDeque::from([entry("a", 1, 60), entry("b", 1, 60), entry("c", 2, 60)]);
+        let user = |id| Some(Requester::new(id));
+        assert_eq!(
+            limits.remaining_songs(&queue, user(1)),
+            Some((1, QueueLimit::UserSongs(3)))
+        );
+        assert_eq!(
+            limits.remaining_songs(&queue, user(3)),
+            Some((2, QueueLimit::QueueLength(5)))
+        );
+        assert_eq!(
+            limits.remaining_songs(&VecDeque::new(), None),
+            Some((4, QueueLimit::PlaylistSize(4)))
+        );
+        assert_eq!(
+            QueueLimits::default().remaining_songs(&queue, user(1)),
+            None
+        );
+    }
+}
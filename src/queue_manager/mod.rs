@@ -1,10 +1,19 @@
+mod fair_queue;
+mod filters;
+mod limits;
+mod permissions;
 mod player;
+mod queue_entry;
 mod queue_saver;
+mod queue_state;
+mod settings;
+mod skip_votes;
 
 use std::{
-    collections::{HashMap, VecDeque},
-    ops::Deref,
+    collections::{HashMap, HashSet, VecDeque},
+    ops::{Deref, RangeInclusive},
     sync::Arc,
+    time::Duration,
 };
 
 use async_trait::async_trait;
@@ -13,32 +22,85 @@ use songbird::{tracks::ControlError, Call, Event, EventContext, EventHandler, Tr
 use tokio::sync::{Mutex, RwLock};
 use tracing::{event, Level};
 
-use crate::common::{Song, SongId};
+use crate::{
+    cache_manager::SongsInUse,
+    common::{Song, SongId},
+};
 
-pub use self::player::LoopMode;
-use self::player::{CurrentSong, Player};
+use self::fair_queue::{fair_insert, fair_order};
+pub use self::filters::{EqBand, FilterKind, Filters};
+pub use self::limits::{LimitedEntries, QueueLimit, QueueLimits};
+pub use self::permissions::CommandPermission;
+use self::player::{prefetch_input, CurrentSong, Player};
+pub use self::player::{LoopMode, PlayListener, SeekPosition};
+pub use self::queue_entry::{QueueEntry, QueuePosition, QueueRange, Requester, SavedEntry};
 pub use self::queue_saver::{FileQueueSaver, QueueSaver};
+pub use self::queue_state::QueueState;
+pub use self::settings::GuildSettings;
+pub use self::skip_votes::SkipVote;
+use self::skip_votes::{required_votes, SkipVotes};
+
+const MAX_HISTORY_LENGTH: usize = 50;
+const SONG_END_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How long before the end of the current song, and its crossfade, the next song is prefetched.
+/// A prefetched input keeps its stream open, so it isn't done earlier
+const PREFETCH_AHEAD: Duration = Duration::from_secs(20);
+
+type Queue = Arc<RwLock<VecDeque<QueueEntry>>>;
+
+/// The queued and the current song of a guild, read by the cache to keep them from being evicted
+pub struct QueuedSongs {
+    queue: Queue,
+    player: Arc<RwLock<Player>>,
+}
 
-type Queue = Arc<RwLock<VecDeque<Box<dyn Song>>>>;
+impl SongsInUse for QueuedSongs {
+    fn songs_in_use(&self) -> Option<Vec<SongId>> {
+        let queue = self.queue.try_read().ok()?;
+        let player = self.player.try_read().ok()?;
+        let mut ids = queue
+            .iter()
+            .map(|e| e.song.get_id().clone())
+            .collect::<Vec<_>>();
+        ids.extend(player.get_current_song().map(|cs| cs.song.get_id().clone()));
+        Some(ids)
+    }
+}
 pub struct QueueManager<QS>
 where
     QS: QueueSaver + Send + Sync,
 {
     queue: Queue,
-    saved_queues: HashMap<String, Vec<SongId>>,
+    history: Queue,
+    saved_queues: HashMap<String, Vec<SavedEntry>>,
     queue_saver: QS,
+    settings: GuildSettings,
     player: Arc<RwLock<Player>>,
+    skip_votes: Option<SkipVotes>,
 }
 impl<QS> QueueManager<QS>
 where
     QS: QueueSaver + Send + Sync,
 {
     pub fn new(queue_saver: QS) -> QueueManager<QS> {
+        let settings = match queue_saver.load_settings() {
+            Ok(settings) => settings,
+            Err(e) => {
+                event!(Level::ERROR, "Failed to load settings: {}", e);
+                GuildSettings::default()
+            }
+        };
         let mut qm = QueueManager {
             queue: Arc::new(RwLock::new(VecDeque::new())),
+            history: Arc::new(RwLock::new(VecDeque::new())),
             saved_queues: HashMap::new(),
-            player: Arc::new(RwLock::new(Player::new())),
+            player: Arc::new(RwLock::new(Player::new(
+                settings.volume,
+                settings.filters.clone(),
+            ))),
+            settings,
             queue_saver,
+            skip_votes: None,
         };
         match qm.queue_saver.load_queues() {
             Ok(queues) => qm.saved_queues = queues,
@@ -48,30 +110,79 @@ where
         }
         qm
     }
+    /// Calls the listener whenever a song starts playing
+    pub async fn set_play_listener(&self, listener: PlayListener) {
+        self.player.write().await.set_play_listener(listener);
+    }
+    pub fn songs_in_use(&self) -> QueuedSongs {
+        QueuedSongs {
+            queue: self.queue.clone(),
+            player: self.player.clone(),
+        }
+    }
     pub async fn add_saved_queue(&mut self, name: impl ToString) -> Result<(), String> {
         let queue_read = self.queue.read().await;
         if queue_read.is_empty() {
             return Err("Queue is empty".to_string());
         }
-        let mut queue: Vec<String> = queue_read.iter().map(|s| s.get_id().clone()).collect();
-        match self.player.read().await.get_current_song() {
-            Some(current_song) => queue.push(current_song.song.get_id().clone()),
-            None => (),
-        };
+        let mut queue: Vec<SavedEntry> = queue_read.iter().map(|e| e.to_saved()).collect();
+        if let Some(current_song) = self.player.read().await.get_current_song() {
+            queue.push(current_song.entry().to_saved());
+        }
         self.saved_queues.insert(name.to_string(), queue);
         Ok(())
     }
-    pub fn get_saved_queue(&self, name: impl ToString) -> Option<Vec<SongId>> {
+    pub fn get_saved_queue(&self, name: impl ToString) -> Option<Vec<SavedEntry>> {
         self.saved_queues.get(&name.to_string()).cloned()
     }
     pub fn remove_saved_queue(&mut self, name: impl ToString) {
         self.saved_queues.remove(&name.to_string());
     }
-    pub fn list_saved_queues(&self) -> Vec<SongId> {
+    pub fn list_saved_queues(&self) -> Vec<String> {
         self.saved_queues.keys().cloned().collect()
     }
-    pub async fn add_to_queue(&self, songs: Vec<Box<dyn Song>>) -> Result<(), ControlError> {
-        self.queue.write().await.append(&mut songs.into());
+    /// Adds the songs at the position, in fair mode songs added to the back are inserted at their
+    /// requester's next turn without moving the queued songs
+    pub async fn add_to_queue(
+        &self,
+        songs: Vec<QueueEntry>,
+        position: QueuePosition,
+    ) -> Result<(), ControlError> {
+        let mut player = self.player.write().await;
+        let mut queue = self.queue.write().await;
+        let interrupted = match position {
+            QueuePosition::Now => player.take_current_song().ok(),
+            _ => None,
+        };
+        if let Some(entry) = &interrupted {
+            // Like every song that stopped playing, so that `previous` can go back to it
+            self.push_history(entry.clone()).await;
+        }
+        let index = match position {
+            QueuePosition::Back => queue.len(),
+            QueuePosition::Next | QueuePosition::Now => 0,
+            QueuePosition::Index(index) => index.min(queue.len()),
+        };
+        let was_playing = interrupted.is_some();
+        if position == QueuePosition::Back && self.settings.fair_queue {
+            let last_requester = player
+                .get_current_song()
+                .and_then(|cs| cs.requester)
+                .map(|r| r.user_id);
+            for entry in songs {
+                fair_insert(&mut queue, entry, last_requester);
+            }
+        } else {
+            for (i, entry) in songs.into_iter().chain(interrupted).enumerate() {
+                queue.insert(index + i, entry);
+            }
+        }
+        drop(queue);
+        drop(player);
+        // Stopping the current song triggers the end event which plays the next song
+        if was_playing {
+            return Ok(());
+        }
         let player = self.player.read().await;
         if let (Some(_c), None) = (player.get_call(), player.get_current_song()) {
             drop(player);
@@ -79,39 +190,74 @@ where
         }
         Ok(())
     }
+    /// Keeps the songs that fit within the queue limits
+    pub async fn limit(&self, songs: Vec<QueueEntry>) -> LimitedEntries {
+        self.settings.limits.apply(&*self.queue.read().await, songs)
+    }
+    /// See `QueueLimits::remaining_songs`
+    pub async fn remaining_songs(
+        &self,
+        requester: Option<Requester>,
+    ) -> Option<(usize, QueueLimit)> {
+        self.settings
+            .limits
+            .remaining_songs(&*self.queue.read().await, requester)
+    }
     pub async fn _remove_from_queue(&self, songs: Vec<SongId>) {
         self.queue
             .write()
             .await
-            .retain(|s| !songs.contains(s.get_id()));
+            .retain(|e| !songs.contains(e.song.get_id()));
     }
-    pub async fn remove_from_queue_by_index(&self, index: usize) -> Option<Box<dyn Song>> {
-        self.queue.write().await.remove(index)
+    /// Removes the songs at the 0-based indices, nothing is removed if the range is out of bounds
+    pub async fn remove_from_queue_by_range(
+        &self,
+        range: RangeInclusive<usize>,
+    ) -> Option<Vec<QueueEntry>> {
+        let mut queue = self.queue.write().await;
+        if *range.end() >= queue.len() {
+            return None;
+        }
+        Some(queue.drain(range).collect())
     }
-    pub async fn get_queue(&self) -> Vec<Box<dyn Song>> {
-        self.queue
-            .read()
-            .await
-            .iter()
-            .map(|s| s.clone_song())
-            .collect()
+    pub async fn remove_requested_by(&self, user_id: u64) -> Vec<QueueEntry> {
+        let mut queue = self.queue.write().await;
+        let (removed, kept): (VecDeque<_>, _) =
+            queue.drain(..).partition(|e| e.requested_by(user_id));
+        *queue = kept;
+        removed.into()
+    }
+    /// Removes the songs that are already earlier in the queue or currently playing
+    pub async fn remove_duplicates(&self) -> Vec<QueueEntry> {
+        let mut seen = HashSet::new();
+        if let Some(current_song) = self.get_current_song().await {
+            seen.insert(current_song.song.get_id().clone());
+        }
+        let mut queue = self.queue.write().await;
+        let (kept, removed): (VecDeque<_>, _) = queue
+            .drain(..)
+            .partition(|e| seen.insert(e.song.get_id().clone()));
+        *queue = kept;
+        removed.into()
+    }
+    pub async fn get_queue(&self) -> Vec<QueueEntry> {
+        self.queue.read().await.iter().cloned().collect()
     }
     pub async fn clear_queue(&mut self) {
         self.queue.write().await.clear();
         let _ = self.skip().await;
     }
-    pub async fn swap(&self, index1: usize, index2: usize) -> Result<(), usize> {
-        let queue_len = self.queue.read().await.len();
-        let index_invalid = |i: usize| i >= queue_len;
-
-        if index_invalid(index1) {
-            return Err(index1);
+    /// Moves the song at `from` so that it ends up at `to`, returns the invalid index otherwise
+    pub async fn move_song(&self, from: usize, to: usize) -> Result<(), usize> {
+        let mut queue = self.queue.write().await;
+        for index in [from, to] {
+            if index >= queue.len() {
+                return Err(index);
+            }
         }
-        if index_invalid(index2) {
-            return Err(index2);
+        if let Some(entry) = queue.remove(from) {
+            queue.insert(to, entry);
         }
-
-        self.queue.write().await.swap(index1, index2);
         Ok(())
     }
     pub fn save_queues(&self) {
@@ -122,6 +268,73 @@ where
             }
         }
     }
+    pub fn save_settings(&self) {
+        match self.queue_saver.save_settings(&self.settings) {
+            Ok(_) => (),
+            Err(e) => {
+                event!(Level::ERROR, "Failed to save settings: {}", e);
+            }
+        }
+    }
+    pub async fn get_state(&self) -> QueueState {
+        let player = self.player.read().await;
+        let (current_song, position) = match player.get_current_song() {
+            Some(cs) => (
+                Some(cs.entry().to_saved()),
+                cs.position().await.unwrap_or_default(),
+            ),
+            None => (None, Duration::ZERO),
+        };
+        QueueState {
+            current_song,
+            position,
+            queue: self
+                .queue
+                .read()
+                .await
+                .iter()
+                .map(|e| e.to_saved())
+                .collect(),
+            loop_mode: player.loop_mode.clone(),
+            channel_id: player.get_channel_id().await,
+        }
+    }
+    pub async fn save_state(&self) {
+        let state = self.get_state().await;
+        match self.queue_saver.save_state(&state) {
+            Ok(_) => (),
+            Err(e) => {
+                event!(Level::ERROR, "Failed to save queue state: {}", e);
+            }
+        }
+    }
+    pub fn load_state(&self) -> Option<QueueState> {
+        match self.queue_saver.load_state() {
+            Ok(state) => state,
+            Err(e) => {
+                event!(Level::ERROR, "Failed to load queue state: {}", e);
+                None
+            }
+        }
+    }
+    /// Restores a previously saved queue state.
+    /// `current_song` is put in front of the queue and resumed at the saved position
+    /// once the player starts
+    pub async fn restore_state(
+        &self,
+        state: &QueueState,
+        current_song: Option<QueueEntry>,
+        songs: Vec<QueueEntry>,
+    ) {
+        let mut player = self.player.write().await;
+        player.loop_mode = state.loop_mode.clone();
+        let mut queue = self.queue.write().await;
+        queue.extend(songs);
+        if let Some(current_song) = current_song {
+            queue.push_front(current_song);
+            player.set_resume_position(state.position);
+        }
+    }
     pub async fn call_joined(
         this: QueueEventHandler<QS>,
         driver: Arc<Mutex<Call>>,
@@ -131,13 +344,20 @@ where
         t.player.write().await.call_joined(driver);
         t.play_next().await?;
         drop(t);
-        call.lock()
-            .await
-            .add_global_event(Event::Track(TrackEvent::End), this);
+        let mut call = call.lock().await;
+        // The call is reused when joining again, its handlers are only registered once
+        call.remove_all_global_events();
+        call.add_global_event(
+            Event::Periodic(SONG_END_CHECK_INTERVAL, None),
+            SongEndEventHandler(this.0.clone()),
+        );
+        call.add_global_event(Event::Track(TrackEvent::End), this);
         Ok(())
     }
     pub async fn call_left(&mut self) -> Option<Arc<Mutex<Call>>> {
-        self.player.write().await.call_left()
+        let call = self.player.write().await.call_left()?;
+        call.lock().await.remove_all_global_events();
+        Some(call)
     }
     pub async fn _get_call(&self) -> Option<Arc<Mutex<Call>>> {
         self.player.read().await.get_call()
@@ -148,59 +368,318 @@ where
     pub async fn resume(&self) -> Result<(), ControlError> {
         self.player.write().await.resume()
     }
+    pub async fn seek(&self, position: SeekPosition) -> Result<Duration, ControlError> {
+        self.player.write().await.seek(position).await
+    }
     pub async fn skip(&self) -> Result<Box<dyn Song>, ControlError> {
-        self.remove_current_song(true).await
+        let entry = self.remove_current_song(true, None).await?;
+        Ok(entry.song)
+    }
+    /// Skips to the song at the index, the songs before it are removed or, if the queue loops,
+    /// moved to the back after the current song. `None` if there is no song at the index
+    pub async fn jump(&self, index: usize) -> Result<Option<Box<dyn Song>>, ControlError> {
+        let (song, skipped) = {
+            let mut queue = self.queue.write().await;
+            if index >= queue.len() {
+                return Ok(None);
+            }
+            let skipped = queue.drain(..index).collect::<Vec<_>>();
+            (queue.front().map(|e| e.song.clone_song()), skipped)
+        };
+        let player = self.player.read().await;
+        let (playing, in_call) = (
+            player.get_current_song().is_some(),
+            player.get_call().is_some(),
+        );
+        let loop_mode = player.loop_mode.clone();
+        drop(player);
+        // Skipping the current song plays the front of the queue
+        let res = match playing {
+            true => self.skip().await.map(|_| ()),
+            false if in_call => self.play_next().await,
+            false => Ok(()),
+        };
+        // A looping queue gets the skipped songs back after the current song
+        if loop_mode == LoopMode::Queue {
+            self.queue.write().await.extend(skipped);
+        }
+        res.map(|_| song)
+    }
+    /// Adds the vote of the user and skips once enough of the listeners voted
+    pub async fn vote_skip(
+        &mut self,
+        user: u64,
+        listeners: &HashSet<u64>,
+    ) -> Result<SkipVote, ControlError> {
+        let current_song = self
+            .get_current_song()
+            .await
+            .ok_or(ControlError::InvalidTrackEvent)?;
+        let votes = match &mut self.skip_votes {
+            Some(votes) if votes.is_for(&current_song.track_handle) => votes,
+            _ => self
+                .skip_votes
+                .insert(SkipVotes::new(current_song.track_handle)),
+        };
+        votes.add(user);
+        let votes = votes.count(listeners);
+        let required = required_votes(listeners.len(), self.settings.vote_skip);
+        if votes < required {
+            return Ok(SkipVote::Voted { votes, required });
+        }
+        self.skip_votes = None;
+        Ok(SkipVote::Skipped(self.skip().await?))
+    }
+    pub fn get_vote_skip(&self) -> u8 {
+        self.settings.vote_skip
+    }
+    pub fn set_vote_skip(&mut self, percentage: u8) {
+        self.settings.vote_skip = percentage;
+        self.save_settings();
+    }
+    pub fn get_dj_role(&self) -> Option<u64> {
+        self.settings.dj_role
+    }
+    pub fn set_dj_role(&mut self, role: Option<u64>) {
+        self.settings.dj_role = role;
+        self.save_settings();
+    }
+    pub fn get_command_permission(&self, command: &str) -> Option<&CommandPermission> {
+        self.settings.permissions.get(command)
+    }
+    pub fn get_command_permissions(&self) -> &HashMap<String, CommandPermission> {
+        &self.settings.permissions
+    }
+    /// Sets who can use the command, without a permission everyone can use it
+    pub fn set_command_permission(
+        &mut self,
+        command: String,
+        permission: Option<CommandPermission>,
+    ) {
+        match permission {
+            Some(permission) => self.settings.permissions.insert(command, permission),
+            None => self.settings.permissions.remove(&command),
+        };
+        self.save_settings();
+    }
+    pub fn get_fair_queue(&self) -> bool {
+        self.settings.fair_queue
+    }
+    pub async fn set_fair_queue(&mut self, enabled: bool) {
+        self.settings.fair_queue = enabled;
+        self.save_settings();
+        if enabled {
+            self.make_fair().await;
+        }
+    }
+    pub fn get_limits(&self) -> &QueueLimits {
+        &self.settings.limits
+    }
+    pub fn set_limits(&mut self, limits: QueueLimits) {
+        self.settings.limits = limits;
+        self.save_settings();
+    }
+    pub fn get_volume(&self) -> f32 {
+        self.settings.volume
+    }
+    pub async fn set_volume(&mut self, volume: f32) -> Result<(), ControlError> {
+        self.settings.volume = volume;
+        self.save_settings();
+        self.player.write().await.set_volume(volume)
+    }
+    pub fn get_filters(&self) -> &Filters {
+        &self.settings.filters
+    }
+    pub async fn set_filters(&mut self, filters: Filters) -> Result<(), ControlError> {
+        self.settings.filters = filters.clone();
+        self.save_settings();
+        self.player.write().await.set_filters(filters).await
+    }
+    pub fn get_crossfade(&self) -> u64 {
+        self.settings.crossfade
+    }
+    pub fn set_crossfade(&mut self, seconds: u64) {
+        self.settings.crossfade = seconds;
+        self.save_settings();
+    }
+    /// Playing time left of the current song, `None` if nothing is playing or the end isn't known
+    async fn remaining(&self) -> Result<Option<Duration>, ControlError> {
+        let current_song = match self.get_current_song().await {
+            Some(current_song) => current_song,
+            None => return Ok(None),
+        };
+        let duration = match current_song.song.duration() {
+            Some(duration) if !current_song.song.is_live() => Duration::from_secs(duration),
+            _ => return Ok(None),
+        };
+        let remaining = duration.saturating_sub(current_song.position().await?);
+        // The rest of the song plays faster or slower than the song time
+        match current_song.filtered {
+            Some(_) => Ok(Some(remaining.div_f32(self.settings.filters.speed))),
+            None => Ok(Some(remaining)),
+        }
+    }
+    /// Prefetches the next song shortly before the current one ends
+    /// and fades into it if crossfading is enabled
+    pub async fn check_song_end(&self) -> Result<(), ControlError> {
+        let remaining = match self.remaining().await? {
+            Some(remaining) => remaining,
+            None => return Ok(()),
+        };
+        let crossfade = self.settings.crossfade();
+        if remaining <= PREFETCH_AHEAD + crossfade.unwrap_or_default() {
+            self.prefetch_next().await;
+        }
+        let crossfade = match crossfade {
+            Some(crossfade) => crossfade,
+            None => return Ok(()),
+        };
+        let loop_mode = self.player.read().await.loop_mode.clone();
+        if loop_mode == LoopMode::None && self.queue.read().await.is_empty() {
+            return Ok(());
+        }
+        if remaining > crossfade || remaining.is_zero() {
+            return Ok(());
+        }
+        self.play_next_with_fade(Some(remaining)).await
+    }
+    /// Plays the last song from the history,
+    /// the current song is kept as the next song in the queue
+    pub async fn previous(&self) -> Result<Option<Box<dyn Song>>, ControlError> {
+        let entry = match self.history.write().await.pop_back() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let mut player = self.player.write().await;
+        let mut queue = self.queue.write().await;
+        let was_playing = match player.take_current_song() {
+            Ok(current_song) => {
+                queue.push_front(current_song);
+                true
+            }
+            Err(_) => false,
+        };
+        let song = entry.song.clone_song();
+        queue.push_front(entry);
+        let in_call = player.get_call().is_some();
+        drop(queue);
+        drop(player);
+        // Stopping the current song triggers the end event which plays the next song
+        if in_call && !was_playing {
+            self.play_next().await?;
+        }
+        Ok(Some(song))
+    }
+    /// Returns the recently played songs, most recent first
+    pub async fn get_history(&self) -> Vec<QueueEntry> {
+        self.history.read().await.iter().rev().cloned().collect()
     }
     pub async fn set_loop(&self, loop_mode: LoopMode) {
         self.player.write().await.loop_mode = loop_mode;
     }
     pub async fn shuffle(&self) {
-        let mut queue = self.queue.write().await;
-        let mut rng = rand::thread_rng();
-        queue.make_contiguous().shuffle(&mut rng);
+        {
+            let mut queue = self.queue.write().await;
+            let mut rng = rand::rng();
+            queue.make_contiguous().shuffle(&mut rng);
+        }
+        if self.settings.fair_queue {
+            self.make_fair().await;
+        }
     }
     pub async fn get_current_song(&self) -> Option<CurrentSong> {
         self.player.read().await.get_current_song()
     }
 
-    async fn remove_current_song(&self, song_skipped: bool) -> Result<Box<dyn Song>, ControlError> {
+    async fn remove_current_song(
+        &self,
+        song_skipped: bool,
+        fade_out: Option<Duration>,
+    ) -> Result<QueueEntry, ControlError> {
         let mut pw = self.player.write().await;
-        let current_song = match pw.take_current_song() {
+        let current_song = match fade_out {
+            Some(duration) => pw.fade_out_current_song(duration).await,
+            None => pw.take_current_song(),
+        };
+        let current_song = match current_song {
             Ok(song) => song,
             Err(e) => {
                 event!(Level::ERROR, "Failed to remove current song: {}", e);
                 return Err(e);
             }
         };
+        self.push_history(current_song.clone()).await;
         let loop_mode = &pw.loop_mode;
         match loop_mode {
             LoopMode::Song => {
                 if !song_skipped {
-                    self.queue
-                        .write()
-                        .await
-                        .push_front(current_song.clone_song())
+                    self.queue.write().await.push_front(current_song.clone())
                 }
             }
             LoopMode::Queue => {
-                self.queue
-                    .write()
-                    .await
-                    .push_back(current_song.clone_song());
+                self.queue.write().await.push_back(current_song.clone());
             }
             LoopMode::None => {}
         }
-        return Ok(current_song);
+        Ok(current_song)
+    }
+    async fn push_history(&self, entry: QueueEntry) {
+        let mut history = self.history.write().await;
+        history.push_back(entry);
+        if history.len() > MAX_HISTORY_LENGTH {
+            history.pop_front();
+        }
+    }
+    /// Reorders the queue so that requesters take turns, starting after the current requester
+    async fn make_fair(&self) {
+        let last_requester = self
+            .get_current_song()
+            .await
+            .and_then(|cs| cs.requester)
+            .map(|r| r.user_id);
+        let mut queue = self.queue.write().await;
+        *queue = fair_order(std::mem::take(&mut *queue), last_requester);
     }
     async fn play_next(&self) -> Result<(), ControlError> {
-        if let Err(e) = self.remove_current_song(false).await {
+        self.play_next_with_fade(None).await
+    }
+    async fn play_next_with_fade(&self, fade: Option<Duration>) -> Result<(), ControlError> {
+        if let Err(e) = self.remove_current_song(false, fade).await {
             event!(Level::ERROR, "Failed to remove current song: {}", e);
         }
-        let song = match self.queue.write().await.pop_front() {
-            Some(song) => song,
-            None => return Ok(()),
+        let (entry, next) = {
+            let mut queue = self.queue.write().await;
+            let entry = match queue.pop_front() {
+                Some(entry) => entry,
+                None => return Ok(()),
+            };
+            (entry, queue.front().map(|e| e.song.get_id().clone()))
         };
-        self.player.write().await.play(song).await
+        self.player
+            .write()
+            .await
+            .play(entry, next.as_ref(), fade)
+            .await?;
+        Ok(())
+    }
+    /// Prepares the input of the next song in the background
+    async fn prefetch_next(&self) {
+        let song = match self.queue.read().await.front() {
+            Some(entry) => entry.song.clone_song(),
+            None => return,
+        };
+        if !self.player.write().await.start_prefetch(song.get_id()) {
+            return;
+        }
+        let player = self.player.clone();
+        tokio::spawn(async move {
+            if let Some(input) = prefetch_input(song.as_ref()).await {
+                player
+                    .write()
+                    .await
+                    .set_prefetched(song.get_id().clone(), input);
+            }
+        });
     }
 }
 
@@ -232,8 +711,17 @@ impl<QS> EventHandler for QueueEventHandler<QS>
 where
     QS: QueueSaver + Send + Sync,
 {
-    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
         let write = self.write().await;
+        // Faded out tracks end after the next song has already started
+        if let (EventContext::Track(tracks), Some(current_song)) =
+            (ctx, write.get_current_song().await)
+        {
+            let uuid = current_song.track_handle.uuid();
+            if tracks.iter().all(|(_, handle)| handle.uuid() != uuid) {
+                return None;
+            }
+        }
         // let _cs = write.remove_current_song(false).await;
         match write.play_next().await {
             Ok(_) => (),
@@ -244,3 +732,22 @@ where
         None
     }
 }
+
+/// Checks periodically whether the current song is about to end
+pub struct SongEndEventHandler<QS>(Arc<RwLock<QueueManager<QS>>>)
+where
+    QS: QueueSaver + Send + Sync;
+
+#[async_trait]
+impl<QS> EventHandler for SongEndEventHandler<QS>
+where
+    QS: QueueSaver + Send + Sync,
+{
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        let read = self.0.read().await;
+        if let Err(e) = read.check_song_end().await {
+            event!(Level::ERROR, "Failed to check the end of the song: {}", e);
+        }
+        None
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serenity::all::Permissions;
+
+/// Commands that change the queue for everyone or the guild's settings, only members who can
+/// manage the server or have the DJ role can use them by default
+const RESTRICTED_COMMANDS: [&str; 13] = [
+    "clear",
+    "remove",
+    "move",
+    "loop",
+    "leave",
+    "remove_saved",
+    "volume",
+    "crossfade",
+    "filter",
+    "voteskip",
+    "djrole",
+    "limits",
+    "fair",
+];
+/// Commands the DJ role doesn't allow, so that DJs can't change who is DJ
+const NOT_FOR_DJS: [&str; 1] = ["djrole"];
+
+/// Who can use a command, administrators can use every command and members with the DJ role
+/// every command but `djrole`
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct CommandPermission {
+    /// Members with any of these roles can use the command
+    pub roles: Vec<u64>,
+    /// Members with all of these permissions can use the command, no permissions allow no one
+    pub permissions: Permissions,
+}
+
+impl CommandPermission {
+    pub fn allows(
+        &self,
+        command: &str,
+        roles: &[u64],
+        permissions: Permissions,
+        dj_role: Option<u64>,
+    ) -> bool {
+        if permissions.administrator() {
+            return true;
+        }
+        let is_dj = dj_role.is_some_and(|role| roles.contains(&role));
+        if is_dj && !NOT_FOR_DJS.contains(&command) {
+            return true;
+        }
+        if self.roles.iter().any(|role| roles.contains(role)) {
+            return true;
+        }
+        !self.permissions.is_empty() && permissions.contains(self.permissions)
+    }
+}
+
+/// Permissions of a new guild, commands that aren't listed can be used by everyone
+pub fn default_permissions() -> HashMap<String, CommandPermission> {
+    RESTRICTED_COMMANDS
+        .iter()
+        .map(|command| {
+            let permission = CommandPermission {
+                roles: vec![],
+                permissions: Permissions::MANAGE_GUILD,
+            };
+            (command.to_string(), permission)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_permission_allows() {
+        let permission = CommandPermission {
+            roles: vec![1],
+            permissions: Permissions::MANAGE_CHANNELS,
+        };
+        let none = Permissions::empty();
+        assert!(permission.allows("clear", &[1], none, None));
+        assert!(permission.allows("clear", &[2], none, Some(2)));
+        assert!(permission.allows("clear", &[], Permissions::MANAGE_CHANNELS, None));
+        assert!(permission.allows("clear", &[], Permissions::ADMINISTRATOR, None));
+        assert!(!permission.allows("clear", &[2], Permissions::SPEAK, Some(3)));
+
+        let roles_only = CommandPermission {
+            roles: vec![1],
+            permissions: Permissions::empty(),
+        };
+        assert!(!roles_only.allows("clear", &[2], Permissions::SPEAK, None));
+    }
+
+    #[test]
+    fn test_default_permissions() {
+        let permissions = default_permissions();
+        assert!(!permissions.contains_key("add"));
+        let clear = permissions.get("clear").expect("clear isn't restricted");
+        assert!(!clear.allows("clear", &[], Permissions::MANAGE_CHANNELS, None));
+        assert!(clear.allows("clear", &[], Permissions::MANAGE_GUILD, None));
+        assert!(clear.allows("clear", &[2], Permissions::empty(), Some(2)));
+    }
+
+    #[test]
+    fn test_command_permission_dj_role() {
+        let permissions = default_permissions();
+        let djrole = permissions.get("djrole").expect("djrole isn't restricted");
+        assert!(!djrole.allows("djrole", &[2], Permissions::empty(), Some(2)));
+        assert!(djrole.allows("djrole", &[2], Permissions::MANAGE_GUILD, Some(2)));
+        assert!(djrole.allows("djrole", &[], Permissions::ADMINISTRATOR, None));
+    }
+}
@@ -1,20 +1,51 @@
-use std::{fmt::Display, str::FromStr, sync::Arc};
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::{Arc, Mutex as SyncMutex},
+    time::Duration,
+};
 
 use poise::ChoiceParameter;
 use serde::{Deserialize, Serialize};
 use songbird::{
-    tracks::{ControlError, TrackHandle},
+    input::{
+        codecs::{CODEC_REGISTRY, PROBE},
+        Input,
+    },
+    tracks::{ControlError, PlayMode, TrackHandle},
     Call,
 };
-use tokio::sync::Mutex;
+use tokio::{sync::Mutex, task::JoinHandle};
 use tracing::{event, Level};
 
-use crate::common::Song;
+use crate::common::{Song, SongId};
+
+use super::{
+    filters::{filter_input, Filters, SongPosition},
+    queue_entry::{QueueEntry, Requester},
+};
 
 pub struct CurrentSong {
     pub song: Box<dyn Song>,
     pub track_handle: TrackHandle,
-    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Loudness normalization applied on top of the volume
+    pub gain: f32,
+    /// Position in the song if the track plays through the filters
+    pub filtered: Option<SongPosition>,
+    pub requester: Option<Requester>,
+}
+
+impl CurrentSong {
+    pub fn entry(&self) -> QueueEntry {
+        QueueEntry::new(self.song.clone_song(), self.requester)
+    }
+    /// Position in the song, which is ahead of the played time when it is sped up
+    pub async fn position(&self) -> Result<Duration, ControlError> {
+        match &self.filtered {
+            Some(position) => Ok(position.get()),
+            None => Ok(self.track_handle.get_info().await?.position),
+        }
+    }
 }
 
 impl Clone for CurrentSong {
@@ -22,15 +53,18 @@ impl Clone for CurrentSong {
         CurrentSong {
             song: self.song.clone_song(),
             track_handle: self.track_handle.clone(),
-            started_at: self.started_at,
+            gain: self.gain,
+            filtered: self.filtered.clone(),
+            requester: self.requester,
         }
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, ChoiceParameter)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, ChoiceParameter)]
 pub enum LoopMode {
     Song,
     Queue,
+    #[default]
     None,
 }
 
@@ -58,17 +92,140 @@ impl FromStr for LoopMode {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeekPosition {
+    Absolute(Duration),
+    Forward(Duration),
+    Backward(Duration),
+}
+
+impl SeekPosition {
+    fn target(&self, current: Duration) -> Duration {
+        match self {
+            SeekPosition::Absolute(d) => *d,
+            SeekPosition::Forward(d) => current + *d,
+            SeekPosition::Backward(d) => current.saturating_sub(*d),
+        }
+    }
+}
+
+fn parse_timestamp(s: &str) -> Option<Duration> {
+    let parts = s.split(':').collect::<Vec<_>>();
+    if parts.is_empty() || parts.len() > 3 {
+        return None;
+    }
+    let mut seconds = 0;
+    for part in parts {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+impl FromStr for SeekPosition {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let res = if let Some(s) = s.strip_prefix('+') {
+            parse_timestamp(s).map(SeekPosition::Forward)
+        } else if let Some(s) = s.strip_prefix('-') {
+            parse_timestamp(s).map(SeekPosition::Backward)
+        } else {
+            parse_timestamp(s).map(SeekPosition::Absolute)
+        };
+        res.ok_or("Invalid seek position")
+    }
+}
+
+const FADE_STEPS: u32 = 20;
+/// Gain of songs whose loudness wasn't measured, streams are usually close to the target already
+const DEFAULT_GAIN: f32 = 1.0;
+
+/// A volume ramp running in the background, it is cancelled when dropped
+struct Fade(JoinHandle<()>);
+
+impl Drop for Fade {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Track of the previous song while it is being faded out
+struct FadingOut {
+    track: TrackHandle,
+    _fade: Fade,
+}
+
+/// Ramps the volume of a track, the track is stopped once it is faded out
+fn fade(track: TrackHandle, from: f32, to: f32, duration: Duration) -> Fade {
+    Fade(tokio::spawn(async move {
+        let step = duration / FADE_STEPS;
+        for i in 1..=FADE_STEPS {
+            tokio::time::sleep(step).await;
+            let volume = from + (to - from) * i as f32 / FADE_STEPS as f32;
+            if track.set_volume(volume).is_err() {
+                return;
+            }
+        }
+        if to == 0.0 {
+            let _ = track.stop();
+        }
+    }))
+}
+
+/// Input of the next song that was made playable ahead of time
+struct Prefetched {
+    id: SongId,
+    input: Input,
+}
+
+/// Creates the input of a song and makes it playable,
+/// so that starting the song doesn't have to wait for yt-dlp or the download
+pub async fn prefetch_input(song: &dyn Song) -> Option<Input> {
+    let input = song.get_input().await;
+    match input.make_playable_async(&CODEC_REGISTRY, &PROBE).await {
+        Ok(input) => Some(input),
+        Err(e) => {
+            event!(Level::WARN, "Failed to prefetch {}: {}", song.get_id(), e);
+            None
+        }
+    }
+}
+
+/// Called with the id of every song that starts playing
+pub type PlayListener = Arc<dyn Fn(&SongId) + Send + Sync>;
+
 pub struct Player {
     call: Option<Arc<Mutex<Call>>>,
     current_song: Option<CurrentSong>,
+    /// Fade in of the current song
+    fade_in: Option<Fade>,
+    fading_out: Option<FadingOut>,
+    // Input isn't Sync, the mutex keeps the player shareable
+    prefetched: SyncMutex<Option<Prefetched>>,
+    /// Song whose input was last prefetched, failed prefetches aren't retried
+    prefetching: Option<SongId>,
+    volume: f32,
+    /// Shared with the filtered tracks, which pick up changes while playing
+    filters: Arc<SyncMutex<Filters>>,
+    resume_position: Option<Duration>,
+    on_play: Option<PlayListener>,
     pub loop_mode: LoopMode,
 }
 
 impl Player {
-    pub fn new() -> Player {
+    pub fn new(volume: f32, filters: Filters) -> Player {
         Player {
             call: None,
             current_song: None,
+            fade_in: None,
+            fading_out: None,
+            prefetched: SyncMutex::new(None),
+            prefetching: None,
+            volume,
+            filters: Arc::new(SyncMutex::new(filters)),
+            resume_position: None,
+            on_play: None,
             loop_mode: LoopMode::None,
         }
     }
@@ -81,21 +238,126 @@ impl Player {
     pub fn get_call(&self) -> Option<Arc<Mutex<Call>>> {
         self.call.clone()
     }
+    pub async fn get_channel_id(&self) -> Option<u64> {
+        let call = self.call.as_ref()?;
+        let channel_id = call.lock().await.current_channel()?;
+        Some(channel_id.0.get())
+    }
+    pub fn set_play_listener(&mut self, listener: PlayListener) {
+        self.on_play = Some(listener);
+    }
+    pub fn set_resume_position(&mut self, position: Duration) {
+        self.resume_position = Some(position);
+    }
+    pub fn is_prefetched(&self, id: &SongId) -> bool {
+        self.prefetched
+            .lock()
+            .expect("Prefetch lock was poisoned")
+            .as_ref()
+            .is_some_and(|p| p.id == *id)
+    }
+    /// Marks the song as being prefetched, false if it already is
+    pub fn start_prefetch(&mut self, id: &SongId) -> bool {
+        if self.prefetching.as_ref() == Some(id) || self.is_prefetched(id) {
+            return false;
+        }
+        self.prefetching = Some(id.clone());
+        true
+    }
+    /// Keeps the input if the song is still the one being prefetched
+    pub fn set_prefetched(&mut self, id: SongId, input: Input) {
+        if self.prefetching.as_ref() != Some(&id) {
+            return;
+        }
+        *self
+            .prefetched
+            .get_mut()
+            .expect("Prefetch lock was poisoned") = Some(Prefetched { id, input });
+    }
+    /// Uses the prefetched input if it belongs to the song. Otherwise it is only kept if it belongs
+    /// to the `next` song, so that streams of removed or skipped songs don't stay open
+    async fn take_input(&mut self, song: &dyn Song, next: Option<&SongId>) -> Input {
+        let prefetched = self
+            .prefetched
+            .get_mut()
+            .expect("Prefetch lock was poisoned");
+        let input = match prefetched.take() {
+            Some(p) if p.id == *song.get_id() => Some(p.input),
+            Some(p) if Some(&p.id) == next => {
+                *prefetched = Some(p);
+                None
+            }
+            _ => None,
+        };
+        if self.prefetching.as_ref() != next {
+            self.prefetching = None;
+        }
+        match input {
+            Some(input) => input,
+            None => song.get_input().await,
+        }
+    }
     pub fn get_current_song(&self) -> Option<CurrentSong> {
         self.current_song.clone()
     }
-    pub fn take_current_song(&mut self) -> Result<Box<dyn Song>, ControlError> {
+    /// Cancels the fade in and sets the current song to its full volume
+    fn finish_fade_in(&mut self) -> Result<(), ControlError> {
+        if self.fade_in.take().is_none() {
+            return Ok(());
+        }
+        match &self.current_song {
+            Some(cs) => cs.track_handle.set_volume(self.volume * cs.gain),
+            None => Ok(()),
+        }
+    }
+    /// Stops the track that is being faded out
+    fn stop_fade_out(&mut self) {
+        if let Some(fading_out) = self.fading_out.take() {
+            let _ = fading_out.track.stop();
+        }
+    }
+    /// Ends a crossfade right away, only the current song keeps playing at its full volume
+    fn finish_fades(&mut self) -> Result<(), ControlError> {
+        self.stop_fade_out();
+        self.finish_fade_in()
+    }
+    pub fn take_current_song(&mut self) -> Result<QueueEntry, ControlError> {
+        self.fade_in = None;
         if let Some(current_song) = self.current_song.take() {
             let res = current_song.track_handle.stop();
             if let Err(e) = res {
                 event!(Level::ERROR, "Failed to stop song: {}", e);
             }
-            Ok(current_song.song)
+            Ok(QueueEntry::new(current_song.song, current_song.requester))
+        } else {
+            Err(ControlError::InvalidTrackEvent)
+        }
+    }
+    /// Takes the current song and fades it out, unlike `take_current_song` the track keeps playing
+    /// so that the next song can be faded in on top of it
+    pub async fn fade_out_current_song(
+        &mut self,
+        duration: Duration,
+    ) -> Result<QueueEntry, ControlError> {
+        self.fade_in = None;
+        if let Some(current_song) = self.current_song.take() {
+            // The song may still be fading in, so the fade out starts at its current volume
+            let volume = match current_song.track_handle.get_info().await {
+                Ok(info) => info.volume,
+                Err(_) => self.volume * current_song.gain,
+            };
+            self.stop_fade_out();
+            self.fading_out = Some(FadingOut {
+                _fade: fade(current_song.track_handle.clone(), volume, 0.0, duration),
+                track: current_song.track_handle,
+            });
+            Ok(QueueEntry::new(current_song.song, current_song.requester))
         } else {
             Err(ControlError::InvalidTrackEvent)
         }
     }
     pub fn pause(&mut self) -> Result<(), ControlError> {
+        self.finish_fades()?;
         if let Some(current_song) = &self.current_song {
             current_song.track_handle.pause()?;
         }
@@ -108,7 +370,59 @@ impl Player {
         }
         Ok(())
     }
-    pub async fn play(&mut self, song: Box<dyn Song>) -> Result<(), ControlError> {
+    /// Sets the volume, a fade in is cancelled while a fade out keeps going to silence
+    pub fn set_volume(&mut self, volume: f32) -> Result<(), ControlError> {
+        self.fade_in = None;
+        self.volume = volume;
+        if let Some(current_song) = &self.current_song {
+            current_song
+                .track_handle
+                .set_volume(volume * current_song.gain)?;
+        }
+        Ok(())
+    }
+    /// Sets the filters, the current song is restarted at its position
+    /// if it doesn't play through the filters yet
+    pub async fn set_filters(&mut self, filters: Filters) -> Result<(), ControlError> {
+        let active = filters.is_active();
+        *self.filters.lock().expect("Filters lock was poisoned") = filters;
+        let current_song = match &self.current_song {
+            Some(cs) if active && cs.filtered.is_none() => cs.clone(),
+            _ => return Ok(()),
+        };
+        let info = current_song.track_handle.get_info().await?;
+        self.resume_position = Some(info.position);
+        // Restarting the song keeps the prefetched input of the next one
+        let next = self.prefetching.clone();
+        self.play(current_song.entry(), next.as_ref(), None).await?;
+        if info.playing == PlayMode::Pause {
+            self.pause()?;
+        }
+        Ok(())
+    }
+    pub async fn seek(&mut self, position: SeekPosition) -> Result<Duration, ControlError> {
+        self.finish_fades()?;
+        let current_song = match &self.current_song {
+            Some(cs) => cs,
+            None => return Err(ControlError::InvalidTrackEvent),
+        };
+        let mut target = position.target(current_song.position().await?);
+        if let Some(duration) = current_song.song.duration() {
+            target = target.min(Duration::from_secs(duration));
+        }
+        // Filtered tracks take the position in song time as well
+        current_song.track_handle.seek_async(target).await
+    }
+    /// Plays the song, with `fade_in` it starts silent and plays alongside the tracks
+    /// that are being faded out. `next` is the song after it in the queue
+    pub async fn play(
+        &mut self,
+        entry: QueueEntry,
+        next: Option<&SongId>,
+        fade_in: Option<Duration>,
+    ) -> Result<(), ControlError> {
+        let QueueEntry { song, requester } = entry;
+        self.fade_in = None;
         let cs = self.current_song.take();
         if let Some(cs) = cs {
             let _ = cs.track_handle.stop();
@@ -117,12 +431,147 @@ impl Player {
             Some(c) => c,
             None => return Err(ControlError::InvalidTrackEvent),
         };
-        let t = call.lock().await.play_only_input(song.get_input().await);
+        let call = call.clone();
+        let input = self.take_input(song.as_ref(), next).await;
+        let filters_active = self
+            .filters
+            .lock()
+            .expect("Filters lock was poisoned")
+            .is_active();
+        let (input, filtered) = if filters_active {
+            match filter_input(input, self.filters.clone()).await {
+                Some((input, position)) => (input, Some(position)),
+                None => (song.get_input().await, None),
+            }
+        } else {
+            (input, None)
+        };
+        let gain = song.gain().await.unwrap_or(DEFAULT_GAIN);
+        let t = match fade_in {
+            Some(_) => call.lock().await.play_input(input),
+            None => {
+                // Playing only this input also stops the track that is still fading out
+                self.fading_out = None;
+                call.lock().await.play_only_input(input)
+            }
+        };
+        let volume = if fade_in.is_some() {
+            0.0
+        } else {
+            self.volume * gain
+        };
+        if let Err(e) = t.set_volume(volume) {
+            event!(Level::ERROR, "Failed to set volume: {}", e);
+        }
+        if let Some(duration) = fade_in {
+            self.fade_in = Some(fade(t.clone(), 0.0, self.volume * gain, duration));
+        }
+        if let Some(position) = self.resume_position.take() {
+            let _ = t.seek(position);
+        }
+        if let Some(on_play) = &self.on_play {
+            on_play(song.get_id());
+        }
         self.current_song = Some(CurrentSong {
             song,
             track_handle: t,
-            started_at: chrono::Utc::now(),
+            gain,
+            filtered,
+            requester,
         });
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs};
+
+    use super::*;
+    use crate::audio_manager::test_utils::{cached_song, test_wav};
+
+    #[tokio::test]
+    async fn test_player_prefetch() {
+        let tempdir = temp_dir().join("test_player_prefetch");
+        fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        fs::write(tempdir.join("song.wav"), test_wav(1)).expect("Failed to write song");
+        let song = cached_song("song", tempdir.join("song.wav"));
+        let other = cached_song("other", tempdir.join("song.wav"));
+        let mut player = Player::new(1.0, Filters::default());
+
+        assert!(player.start_prefetch(&song.id));
+        let input = prefetch_input(&song).await.expect("Failed to prefetch");
+        assert!(matches!(input, Input::Live(..)));
+        player.set_prefetched(song.id.clone(), input);
+        assert!(player.is_prefetched(&song.id));
+        assert!(matches!(
+            player.take_input(&other, Some(&song.id)).await,
+            Input::Lazy(..)
+        ));
+        assert!(player.is_prefetched(&song.id));
+        assert!(matches!(
+            player.take_input(&song, None).await,
+            Input::Live(..)
+        ));
+        assert!(!player.is_prefetched(&song.id));
+
+        // The input of a song that was skipped or removed is dropped
+        assert!(player.start_prefetch(&song.id));
+        let input = prefetch_input(&song).await.expect("Failed to prefetch");
+        player.set_prefetched(song.id.clone(), input);
+        assert!(matches!(
+            player.take_input(&other, None).await,
+            Input::Lazy(..)
+        ));
+        assert!(!player.is_prefetched(&song.id));
+
+        assert!(player.start_prefetch(&other.id));
+        assert!(!player.start_prefetch(&other.id));
+        assert!(
+            prefetch_input(&cached_song("missing", tempdir.join("missing.wav")))
+                .await
+                .is_none()
+        );
+        fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+
+    #[test]
+    fn test_seek_position_from_str() {
+        assert_eq!(
+            "1:23".parse(),
+            Ok(SeekPosition::Absolute(Duration::from_secs(83)))
+        );
+        assert_eq!(
+            "1:02:03".parse(),
+            Ok(SeekPosition::Absolute(Duration::from_secs(3723)))
+        );
+        assert_eq!(
+            "+30".parse(),
+            Ok(SeekPosition::Forward(Duration::from_secs(30)))
+        );
+        assert_eq!(
+            "-15".parse(),
+            Ok(SeekPosition::Backward(Duration::from_secs(15)))
+        );
+        assert!("1:2:3:4".parse::<SeekPosition>().is_err());
+        assert!("abc".parse::<SeekPosition>().is_err());
+        assert!("".parse::<SeekPosition>().is_err());
+    }
+
+    #[test]
+    fn test_seek_position_target() {
+        let current = Duration::from_secs(10);
+        assert_eq!(
+            SeekPosition::Forward(Duration::from_secs(30)).target(current),
+            Duration::from_secs(40)
+        );
+        assert_eq!(
+            SeekPosition::Backward(Duration::from_secs(15)).target(current),
+            Duration::ZERO
+        );
+        assert_eq!(
+            SeekPosition::Absolute(Duration::from_secs(5)).target(current),
+            Duration::from_secs(5)
+        );
+    }
+}
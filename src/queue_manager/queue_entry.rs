@@ -0,0 +1,166 @@
+use std::{ops::RangeInclusive, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::{Song, SongId};
+
+/// Who added a song to the queue and when
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Requester {
+    pub user_id: u64,
+    /// Unix timestamp in seconds
+    pub requested_at: i64,
+}
+
+impl Requester {
+    pub fn new(user_id: u64) -> Self {
+        Requester {
+            user_id,
+            requested_at: chrono::Utc::now().timestamp(),
+        }
+    }
+}
+
+/// A song in the queue, songs restored from before requesters were tracked have none
+pub struct QueueEntry {
+    pub song: Box<dyn Song>,
+    pub requester: Option<Requester>,
+}
+
+impl QueueEntry {
+    pub fn new(song: Box<dyn Song>, requester: Option<Requester>) -> Self {
+        QueueEntry { song, requester }
+    }
+    pub fn requested_by(&self, user_id: u64) -> bool {
+        self.requester.is_some_and(|r| r.user_id == user_id)
+    }
+    pub fn to_saved(&self) -> SavedEntry {
+        SavedEntry {
+            id: self.song.get_id().clone(),
+            requester: self.requester,
+        }
+    }
+}
+
+impl Clone for QueueEntry {
+    fn clone(&self) -> Self {
+        QueueEntry {
+            song: self.song.clone_song(),
+            requester: self.requester,
+        }
+    }
+}
+
+/// Where songs are added to the queue
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueuePosition {
+    Back,
+    /// Before the rest of the queue
+    Next,
+    /// Interrupts the current song, which plays again after the added songs
+    Now,
+    /// Before the song at the 0-based index, past the end of the queue it appends
+    Index(usize),
+}
+
+/// Songs in the queue by their 1-based positions, a single position (3) or a range (3-10)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QueueRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl QueueRange {
+    /// The 0-based indices of the songs
+    pub fn indices(&self) -> RangeInclusive<usize> {
+        self.start - 1..=self.end - 1
+    }
+}
+
+impl FromStr for QueueRange {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |s: &str| s.trim().parse::<usize>().ok().filter(|&i| i > 0);
+        let res = match s.split_once('-') {
+            Some((start, end)) => parse(start).zip(parse(end)),
+            None => parse(s).map(|i| (i, i)),
+        };
+        match res {
+            Some((start, end)) if start <= end => Ok(QueueRange { start, end }),
+            _ => Err("Invalid range"),
+        }
+    }
+}
+
+/// A queue entry as it is saved, older files only contain the song id
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(from = "SavedEntryFormat")]
+pub struct SavedEntry {
+    pub id: SongId,
+    pub requester: Option<Requester>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedEntryFormat {
+    Id(SongId),
+    Entry {
+        id: SongId,
+        #[serde(default)]
+        requester: Option<Requester>,
+    },
+}
+
+impl From<SavedEntryFormat> for SavedEntry {
+    fn from(format: SavedEntryFormat) -> Self {
+        match format {
+            SavedEntryFormat::Id(id) => SavedEntry {
+                id,
+                requester: None,
+            },
+            SavedEntryFormat::Entry { id, requester } => SavedEntry { id, requester },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_range_from_str() {
+        assert_eq!("3".parse(), Ok(QueueRange { start: 3, end: 3 }));
+        assert_eq!("3-10".parse(), Ok(QueueRange { start: 3, end: 10 }));
+        assert_eq!(" 3 - 10 ".parse(), Ok(QueueRange { start: 3, end: 10 }));
+        assert_eq!(QueueRange { start: 3, end: 10 }.indices(), 2..=9);
+        assert!("0".parse::<QueueRange>().is_err());
+        assert!("10-3".parse::<QueueRange>().is_err());
+        assert!("3-".parse::<QueueRange>().is_err());
+        assert!("abc".parse::<QueueRange>().is_err());
+    }
+
+    #[test]
+    fn test_saved_entry_deserialize() {
+        let entries: Vec<SavedEntry> = serde_json::from_str(
+            r#"["old", {"id": "new", "requester": {"user_id": 1, "requested_at": 2}}]"#,
+        )
+        .expect("Failed to deserialize");
+        assert_eq!(
+            entries,
+            vec![
+                SavedEntry {
+                    id: "old".to_string(),
+                    requester: None,
+                },
+                SavedEntry {
+                    id: "new".to_string(),
+                    requester: Some(Requester {
+                        user_id: 1,
+                        requested_at: 2,
+                    }),
+                },
+            ]
+        );
+    }
+}
@@ -4,43 +4,82 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::common::SongId;
+use super::{GuildSettings, QueueState, SavedEntry};
 
 const SAVED_QUEUES_FILE_NAME: &str = "saved_queues.json";
+const SETTINGS_FILE_NAME: &str = "settings.json";
+const QUEUE_STATE_FILE_NAME: &str = "queue_state.json";
 
 pub trait QueueSaver: Send + Sync + 'static {
-    fn save_queues(&self, queues: HashMap<String, Vec<SongId>>) -> Result<(), String>;
-    fn load_queues(&self) -> Result<HashMap<String, Vec<SongId>>, String>;
+    fn save_queues(&self, queues: HashMap<String, Vec<SavedEntry>>) -> Result<(), String>;
+    fn load_queues(&self) -> Result<HashMap<String, Vec<SavedEntry>>, String>;
+    fn save_settings(&self, settings: &GuildSettings) -> Result<(), String>;
+    fn load_settings(&self) -> Result<GuildSettings, String>;
+    fn save_state(&self, state: &QueueState) -> Result<(), String>;
+    /// `None` if no state was saved
+    fn load_state(&self) -> Result<Option<QueueState>, String>;
 }
 
-
-
 pub struct FileQueueSaver {
     saved_queues_path: PathBuf,
+    settings_path: PathBuf,
+    queue_state_path: PathBuf,
 }
 
 impl FileQueueSaver {
     pub fn new(saved_queues_path: impl AsRef<OsStr>) -> FileQueueSaver {
         FileQueueSaver {
             saved_queues_path: Path::new(&saved_queues_path).join(SAVED_QUEUES_FILE_NAME),
+            settings_path: Path::new(&saved_queues_path).join(SETTINGS_FILE_NAME),
+            queue_state_path: Path::new(&saved_queues_path).join(QUEUE_STATE_FILE_NAME),
         }
     }
 }
 
 impl QueueSaver for FileQueueSaver {
-    fn save_queues(&self, queues: HashMap<String, Vec<SongId>>) -> Result<(), String> {
+    fn save_queues(&self, queues: HashMap<String, Vec<SavedEntry>>) -> Result<(), String> {
         println!("Saving queues to {:?}", &self.saved_queues_path);
         let file = std::fs::File::create(&self.saved_queues_path).map_err(|e| e.to_string())?;
         serde_json::to_writer(file, &queues).map_err(|e| e.to_string())?;
         Ok(())
     }
 
-    fn load_queues(&self) -> Result<HashMap<String, Vec<SongId>>, String> {
+    fn load_queues(&self) -> Result<HashMap<String, Vec<SavedEntry>>, String> {
         let file = std::fs::File::open(&self.saved_queues_path).map_err(|e| e.to_string())?;
         serde_json::from_reader(file).map_err(|e| e.to_string())
     }
+
+    fn save_settings(&self, settings: &GuildSettings) -> Result<(), String> {
+        let file = std::fs::File::create(&self.settings_path).map_err(|e| e.to_string())?;
+        serde_json::to_writer(file, settings).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn load_settings(&self) -> Result<GuildSettings, String> {
+        let file = std::fs::File::open(&self.settings_path).map_err(|e| e.to_string())?;
+        serde_json::from_reader(file).map_err(|e| e.to_string())
+    }
+
+    fn save_state(&self, state: &QueueState) -> Result<(), String> {
+        let file = std::fs::File::create(&self.queue_state_path).map_err(|e| e.to_string())?;
+        serde_json::to_writer(file, state).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn load_state(&self) -> Result<Option<QueueState>, String> {
+        let file = match std::fs::File::open(&self.queue_state_path) {
+            Ok(file) => file,
+            // Guilds that never played anything have no saved state
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        serde_json::from_reader(file)
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
 }
 
+#[allow(dead_code)]
 pub struct NullQueueSaver {}
 
 impl NullQueueSaver {
@@ -50,13 +89,29 @@ impl NullQueueSaver {
 }
 
 impl QueueSaver for NullQueueSaver {
-    fn save_queues(&self, _: HashMap<String, Vec<SongId>>) -> Result<(), String> {
+    fn save_queues(&self, _: HashMap<String, Vec<SavedEntry>>) -> Result<(), String> {
         Ok(())
     }
 
-    fn load_queues(&self) -> Result<HashMap<String, Vec<SongId>>, String> {
+    fn load_queues(&self) -> Result<HashMap<String, Vec<SavedEntry>>, String> {
         Ok(HashMap::new())
     }
+
+    fn save_settings(&self, _: &GuildSettings) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn load_settings(&self) -> Result<GuildSettings, String> {
+        Ok(GuildSettings::default())
+    }
+
+    fn save_state(&self, _: &QueueState) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn load_state(&self) -> Result<Option<QueueState>, String> {
+        Ok(None)
+    }
 }
 
 #[cfg(test)]
@@ -64,6 +119,7 @@ mod tests {
     use std::env::temp_dir;
 
     use super::*;
+    use crate::queue_manager::{Filters, LoopMode, Requester};
 
     #[test]
     fn test_file_queue_saver_save_and_load_queues() {
@@ -71,10 +127,77 @@ mod tests {
         std::fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
         let saver = FileQueueSaver::new(&tempdir);
         let mut queues = HashMap::new();
-        queues.insert("test".to_string(), vec!["test".to_string()]);
-        saver.save_queues(queues.clone()).expect("Failed to save queues");
+        let entry = SavedEntry {
+            id: "test".to_string(),
+            requester: Some(Requester::new(1)),
+        };
+        queues.insert("test".to_string(), vec![entry]);
+        saver
+            .save_queues(queues.clone())
+            .expect("Failed to save queues");
         let res = saver.load_queues().expect("Failed to load queues");
         assert_eq!(res, queues);
         std::fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
     }
+
+    #[test]
+    fn test_file_queue_saver_save_and_load_settings() {
+        let tempdir = temp_dir().join("test_file_queue_saver_save_and_load_settings");
+        std::fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        let saver = FileQueueSaver::new(&tempdir);
+        let settings = GuildSettings {
+            volume: 0.5,
+            crossfade: 5,
+            filters: Filters {
+                mono: true,
+                ..Default::default()
+            },
+            vote_skip: 50,
+            dj_role: Some(42),
+            ..Default::default()
+        };
+        saver
+            .save_settings(&settings)
+            .expect("Failed to save settings");
+        let res = saver.load_settings().expect("Failed to load settings");
+        assert_eq!(res, settings);
+        std::fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+
+    #[test]
+    fn test_file_queue_saver_save_and_load_state() {
+        let tempdir = temp_dir().join("test_file_queue_saver_save_and_load_state");
+        std::fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        let saver = FileQueueSaver::new(&tempdir);
+        let state = QueueState {
+            current_song: Some(SavedEntry {
+                id: "current".to_string(),
+                requester: Some(Requester::new(1)),
+            }),
+            position: std::time::Duration::from_secs(42),
+            queue: vec![SavedEntry {
+                id: "next".to_string(),
+                requester: None,
+            }],
+            loop_mode: LoopMode::Queue,
+            channel_id: Some(1),
+        };
+        saver.save_state(&state).expect("Failed to save state");
+        let res = saver.load_state().expect("Failed to load state");
+        assert_eq!(res, Some(state));
+        std::fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
+
+    #[test]
+    fn test_file_queue_saver_load_missing_state() {
+        let tempdir = temp_dir().join("test_file_queue_saver_load_missing_state");
+        std::fs::create_dir_all(&tempdir).expect("Failed to create temp dir");
+        let saver = FileQueueSaver::new(&tempdir);
+        assert_eq!(saver.load_state(), Ok(None));
+
+        std::fs::write(tempdir.join(QUEUE_STATE_FILE_NAME), "not json")
+            .expect("Failed to write state");
+        assert!(saver.load_state().is_err());
+        std::fs::remove_dir_all(&tempdir).expect("Failed to remove temp dir");
+    }
 }
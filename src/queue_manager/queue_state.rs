@@ -0,0 +1,15 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{LoopMode, SavedEntry};
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct QueueState {
+    pub current_song: Option<SavedEntry>,
+    pub position: Duration,
+    pub queue: Vec<SavedEntry>,
+    pub loop_mode: LoopMode,
+    pub channel_id: Option<u64>,
+}
@@ -0,0 +1,49 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::{permissions::default_permissions, CommandPermission, Filters, QueueLimits};
+
+const DEFAULT_VOLUME: f32 = 1.0;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct GuildSettings {
+    pub volume: f32,
+    /// Seconds the end of a song overlaps with the start of the next one, 0 disables crossfade
+    pub crossfade: u64,
+    pub filters: Filters,
+    /// Percentage of the listeners that have to vote to skip a song, 0 lets everyone skip
+    pub vote_skip: u8,
+    /// Members with this role can skip without voting
+    pub dj_role: Option<u64>,
+    /// Who can use each command, keyed by the qualified command name
+    pub permissions: HashMap<String, CommandPermission>,
+    /// Requesters take turns instead of songs playing in the order they were added
+    pub fair_queue: bool,
+    pub limits: QueueLimits,
+}
+
+impl GuildSettings {
+    pub fn crossfade(&self) -> Option<Duration> {
+        match self.crossfade {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+    }
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        GuildSettings {
+            volume: DEFAULT_VOLUME,
+            crossfade: 0,
+            filters: Filters::default(),
+            vote_skip: 0,
+            dj_role: None,
+            permissions: default_permissions(),
+            fair_queue: false,
+            limits: QueueLimits::default(),
+        }
+    }
+}
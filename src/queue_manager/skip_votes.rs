@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+
+use songbird::tracks::TrackHandle;
+
+use crate::common::Song;
+
+pub enum SkipVote {
+    Skipped(Box<dyn Song>),
+    Voted { votes: usize, required: usize },
+}
+
+/// Users who voted to skip a track, a new track starts without votes
+pub struct SkipVotes {
+    track: TrackHandle,
+    users: HashSet<u64>,
+}
+
+impl SkipVotes {
+    pub fn new(track: TrackHandle) -> Self {
+        SkipVotes {
+            track,
+            users: HashSet::new(),
+        }
+    }
+
+    pub fn is_for(&self, track: &TrackHandle) -> bool {
+        self.track.uuid() == track.uuid()
+    }
+
+    pub fn add(&mut self, user: u64) {
+        self.users.insert(user);
+    }
+
+    /// Counts the votes of users who are still listening
+    pub fn count(&self, listeners: &HashSet<u64>) -> usize {
+        self.users.intersection(listeners).count()
+    }
+}
+
+/// Votes needed to skip with the given percentage of the listeners, at least one
+pub fn required_votes(listeners: usize, percentage: u8) -> usize {
+    (listeners * percentage as usize).div_ceil(100).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_votes() {
+        assert_eq!(required_votes(4, 50), 2);
+        assert_eq!(required_votes(5, 50), 3);
+        assert_eq!(required_votes(3, 100), 3);
+        assert_eq!(required_votes(1, 10), 1);
+        assert_eq!(required_votes(0, 50), 1);
+    }
+}